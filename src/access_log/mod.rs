@@ -0,0 +1,408 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::server_utils::{AccessLogConfig, LogFormat};
+use rotation::RotatingFile;
+
+pub mod rotation;
+
+/**
+ * A request served by the HTTP server.
+ */
+pub struct HttpEntry{
+    pub peer: IpAddr,
+    pub user: Option<String>,
+    pub time: SystemTime,
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub status: u16,
+    // bytes of the response body which were sent
+    pub bytes: u64,
+    pub duration: Duration,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>
+}
+
+/**
+ * A command handled by the FTP server.
+ */
+pub struct FtpEntry{
+    pub peer: IpAddr,
+    pub user: Option<String>,
+    pub time: SystemTime,
+    // the command as received, with any password replaced
+    pub command: String,
+    pub reply_code: u16,
+    pub duration: Duration
+}
+
+/**
+ * Lines waiting for the writer, past which new lines are dropped rather than held in memory.
+ */
+const QUEUED_LINES: usize = 8192;
+
+enum LogOutput{
+    Stdout,
+    File(RotatingFile)
+}
+
+/**
+ * What is sent to the writer thread.
+ */
+enum Message{
+    Line(String),
+    // answered once every line sent before it has been written
+    Flush(std::sync::mpsc::Sender<()>)
+}
+
+/**
+ * Writes one line for each HTTP request and FTP command to the configured output.
+ *
+ * Lines are formatted by the caller, and written, along with any rotation, by a thread of their
+ * own, so that a slow disk never holds up the runtime serving connections.
+ */
+pub struct AccessLog{
+    format: LogFormat,
+    lines: SyncSender<Message>,
+    // lines dropped because the writer had fallen behind, reported by the writer
+    dropped: Arc<AtomicU64>
+}
+
+impl AccessLog{
+    /**
+     * Open the access log described by the server configuration.
+     *
+     * # Arguments
+     * * `config` - The access log section of the configuration.
+     */
+    pub fn from_config(config: &AccessLogConfig) -> std::io::Result<Self>{
+        let output = match &config.path{
+            Some(path) => LogOutput::File(RotatingFile::open(
+                path,
+                config.max_size,
+                config.rotate_interval.map(Duration::from_secs),
+                config.keep
+            )?),
+            None => LogOutput::Stdout
+        };
+        let (lines, receiver) = std::sync::mpsc::sync_channel(QUEUED_LINES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = Arc::clone(&dropped);
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || write_lines(output, receiver, writer_dropped))?;
+        Ok(Self{ format: config.format, lines, dropped })
+    }
+
+    /**
+     * Wait until every line logged so far has been written, such as before the server exits.
+     */
+    pub fn flush(&self){
+        let (done, written) = std::sync::mpsc::channel();
+        if self.lines.send(Message::Flush(done)).is_ok(){
+            let _ = written.recv();
+        }
+    }
+
+    /**
+     * Log a request served by the HTTP server.
+     *
+     * # Arguments
+     * * `entry` - The request.
+     */
+    pub fn log_http(&self, entry: &HttpEntry){
+        let request_line = format!("{} {} {}", entry.method, entry.target, entry.version);
+        let line = match self.format{
+            LogFormat::Common => format!(
+                "{} - {} [{}] \"{}\" {} {}",
+                entry.peer, clf_field(entry.user.as_deref()), clf_time(entry.time),
+                escape(&request_line), entry.status, entry.bytes
+            ),
+            LogFormat::Combined => format!(
+                "{} - {} [{}] \"{}\" {} {} \"{}\" \"{}\"",
+                entry.peer, clf_field(entry.user.as_deref()), clf_time(entry.time),
+                escape(&request_line), entry.status, entry.bytes,
+                escape(entry.referer.as_deref().unwrap_or("-")), escape(entry.user_agent.as_deref().unwrap_or("-"))
+            ),
+            LogFormat::Extended => format!(
+                "{} - {} [{}] \"{}\" {} {} \"{}\" \"{}\" {}",
+                entry.peer, clf_field(entry.user.as_deref()), clf_time(entry.time),
+                escape(&request_line), entry.status, entry.bytes,
+                escape(entry.referer.as_deref().unwrap_or("-")), escape(entry.user_agent.as_deref().unwrap_or("-")),
+                entry.duration.as_millis()
+            ),
+            LogFormat::Json => serde_json::json!({
+                "time": rfc3339_time(entry.time),
+                "protocol": "http",
+                "peer": entry.peer.to_string(),
+                "user": entry.user,
+                "method": entry.method,
+                "target": entry.target,
+                "version": entry.version,
+                "status": entry.status,
+                "bytes": entry.bytes,
+                "duration_ms": entry.duration.as_secs_f64() * 1000.0,
+                "referer": entry.referer,
+                "user_agent": entry.user_agent
+            }).to_string()
+        };
+        self.write_line(line);
+    }
+
+    /**
+     * Log a command handled by the FTP server.
+     *
+     * In the Common, Combined and Extended formats the command takes the place of the request
+     * line, and its reply code the place of the status.
+     *
+     * # Arguments
+     * * `entry` - The command.
+     */
+    pub fn log_ftp(&self, entry: &FtpEntry){
+        let line = match self.format{
+            LogFormat::Common => format!(
+                "{} - {} [{}] \"{}\" {} -",
+                entry.peer, clf_field(entry.user.as_deref()), clf_time(entry.time),
+                escape(&entry.command), entry.reply_code
+            ),
+            LogFormat::Combined => format!(
+                "{} - {} [{}] \"{}\" {} - \"-\" \"-\"",
+                entry.peer, clf_field(entry.user.as_deref()), clf_time(entry.time),
+                escape(&entry.command), entry.reply_code
+            ),
+            LogFormat::Extended => format!(
+                "{} - {} [{}] \"{}\" {} - \"-\" \"-\" {}",
+                entry.peer, clf_field(entry.user.as_deref()), clf_time(entry.time),
+                escape(&entry.command), entry.reply_code, entry.duration.as_millis()
+            ),
+            LogFormat::Json => serde_json::json!({
+                "time": rfc3339_time(entry.time),
+                "protocol": "ftp",
+                "peer": entry.peer.to_string(),
+                "user": entry.user,
+                "command": entry.command,
+                "reply_code": entry.reply_code,
+                "duration_ms": entry.duration.as_secs_f64() * 1000.0
+            }).to_string()
+        };
+        self.write_line(line);
+    }
+
+    fn write_line(&self, mut line: String){
+        line.push('\n');
+        match self.lines.try_send(Message::Line(line)){
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(TrySendError::Disconnected(_)) => tracing::error!("the access log writer has stopped")
+        }
+    }
+}
+
+/**
+ * Write lines to the output of the access log until every sender is gone.
+ *
+ * # Arguments
+ * * `output` - Where lines are written.
+ * * `messages` - The lines to write, and requests to flush.
+ * * `dropped` - Counts the lines dropped because the queue was full.
+ */
+fn write_lines(mut output: LogOutput, messages: Receiver<Message>, dropped: Arc<AtomicU64>){
+    for message in messages{
+        let line = match message{
+            Message::Line(line) => line,
+            Message::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            }
+        };
+        let result = match &mut output{
+            LogOutput::Stdout => {
+                print!("{line}");
+                Ok(())
+            },
+            LogOutput::File(file) => file.write_line(&line)
+        };
+        if let Err(e) = result{
+            tracing::error!(error = %e, "could not write to the access log");
+        }
+        let dropped = dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0{
+            tracing::warn!(dropped, "dropped access log lines, the log could not keep up");
+        }
+    }
+}
+
+fn clf_field(value: Option<&str>) -> String{
+    match value{
+        Some(value) if !value.is_empty() => escape(value).replace(' ', "_"),
+        _ => "-".to_string()
+    }
+}
+
+/**
+ * Escape the characters which would break a quoted field, or the line itself.
+ */
+fn escape(value: &str) -> String{
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars(){
+        match c{
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/**
+ * Format a time as in the Common Log Format, such as "10/Oct/2000:13:55:36 +0000".
+ */
+fn clf_time(time: SystemTime) -> String{
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (year, month, day, hour, minute, second) = utc_fields(time);
+    format!("{day:02}/{}/{year}:{hour:02}:{minute:02}:{second:02} +0000", MONTHS[month as usize - 1])
+}
+
+/**
+ * Format a time as in RFC 3339, such as "2000-10-10T13:55:36Z".
+ */
+fn rfc3339_time(time: SystemTime) -> String{
+    let (year, month, day, hour, minute, second) = utc_fields(time);
+    format!("{year}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/**
+ * Split a time into its UTC year, month, day, hour, minute and second.
+ */
+fn utc_fields(time: SystemTime) -> (i64, u32, u32, u32, u32, u32){
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0) as i64;
+    let (days, seconds_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // civil date from days since the epoch, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {shifted_month + 3} else {shifted_month - 9} as u32;
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+
+    (
+        year,
+        month,
+        day,
+        (seconds_of_day / 3600) as u32,
+        (seconds_of_day % 3600 / 60) as u32,
+        (seconds_of_day % 60) as u32
+    )
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime{
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn splits_times_into_utc_fields(){
+        assert_eq!(utc_fields(at(0)), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(utc_fields(at(971186136)), (2000, 10, 10, 13, 55, 36));
+        // times before the epoch cannot be represented, and are logged as the epoch
+        assert_eq!(utc_fields(UNIX_EPOCH - Duration::from_secs(1)), (1970, 1, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn leap_days(){
+        // 2000 is divisible by 400, so it is a leap year
+        assert_eq!(utc_fields(at(951827696)), (2000, 2, 29, 12, 34, 56));
+        assert_eq!(utc_fields(at(1709251199)), (2024, 2, 29, 23, 59, 59));
+        assert_eq!(utc_fields(at(1709251200)), (2024, 3, 1, 0, 0, 0));
+        // 2100 is divisible by 100 but not 400, so it is not
+        assert_eq!(utc_fields(at(4107542399)), (2100, 2, 28, 23, 59, 59));
+        assert_eq!(utc_fields(at(4107542400)), (2100, 3, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn year_boundaries(){
+        assert_eq!(utc_fields(at(946684799)), (1999, 12, 31, 23, 59, 59));
+        assert_eq!(utc_fields(at(946684800)), (2000, 1, 1, 0, 0, 0));
+        assert_eq!(clf_time(at(1704067199)), "31/Dec/2023:23:59:59 +0000");
+        assert_eq!(clf_time(at(1704067200)), "01/Jan/2024:00:00:00 +0000");
+        assert_eq!(rfc3339_time(at(1704067199)), "2023-12-31T23:59:59Z");
+        assert_eq!(rfc3339_time(at(1704067200)), "2024-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn formats_times(){
+        assert_eq!(clf_time(at(971186136)), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(clf_time(at(951827696)), "29/Feb/2000:12:34:56 +0000");
+        assert_eq!(rfc3339_time(at(971186136)), "2000-10-10T13:55:36Z");
+        // sub-second parts are dropped
+        assert_eq!(rfc3339_time(at(971186136) + Duration::from_millis(999)), "2000-10-10T13:55:36Z");
+    }
+
+    #[test]
+    fn escapes_quoted_fields(){
+        assert_eq!(escape("GET /a b HTTP/1.1"), "GET /a b HTTP/1.1");
+        assert_eq!(escape("say \"hi\""), "say \\\"hi\\\"");
+        assert_eq!(escape("back\\slash"), "back\\\\slash");
+        assert_eq!(escape("line\nbreak\r\t\u{7f}"), "line\\x0abreak\\x0d\\x09\\x7f");
+        assert_eq!(escape("caf\u{e9}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn escapes_unquoted_fields(){
+        assert_eq!(clf_field(None), "-");
+        assert_eq!(clf_field(Some("")), "-");
+        assert_eq!(clf_field(Some("alice")), "alice");
+        assert_eq!(clf_field(Some("alice smith\n")), "alice_smith\\x0a");
+    }
+
+    #[test]
+    fn writes_each_format(){
+        let directory = tempfile::tempdir().unwrap();
+        let entry = HttpEntry{
+            peer: "192.0.2.1".parse().unwrap(),
+            user: Some("alice".to_string()),
+            time: at(971186136),
+            method: "GET".to_string(),
+            target: "/a\"b".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes: 2326,
+            duration: Duration::from_millis(12),
+            referer: None,
+            user_agent: Some("curl/8.0".to_string())
+        };
+        let expected = [
+            (LogFormat::Common, "192.0.2.1 - alice [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326"),
+            (LogFormat::Combined, "192.0.2.1 - alice [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326 \"-\" \"curl/8.0\""),
+            (LogFormat::Extended, "192.0.2.1 - alice [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326 \"-\" \"curl/8.0\" 12")
+        ];
+        for (format, line) in expected{
+            let path = directory.path().join("access.log");
+            let log = AccessLog::from_config(&AccessLogConfig{
+                format,
+                path: Some(path.to_str().unwrap().to_string()),
+                max_size: None,
+                rotate_interval: None,
+                keep: 0
+            }).unwrap();
+            log.log_http(&entry);
+            log.flush();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{line}\n"));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
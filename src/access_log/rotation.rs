@@ -0,0 +1,164 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/**
+ * A log file which is rotated when it grows too large or too old.
+ *
+ * Rotating renames the file to "path.1", shifting older files up by one, and drops the oldest
+ * once there are more than `keep`.
+ */
+pub struct RotatingFile{
+    path: PathBuf,
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    keep: usize,
+    file: File,
+    size: u64,
+    opened_at: SystemTime
+}
+
+impl RotatingFile{
+    /**
+     * Open a log file for appending, creating it if needed.
+     *
+     * # Arguments
+     * * `path` - The path of the log file.
+     * * `max_size` - The size in bytes past which the file is rotated.
+     * * `interval` - How long the file is written to before it is rotated.
+     * * `keep` - How many rotated files are kept.
+     */
+    pub fn open<P: AsRef<Path>>(path: P, max_size: Option<u64>, interval: Option<Duration>, keep: usize) -> std::io::Result<Self>{
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self{
+            path,
+            max_size,
+            interval,
+            keep,
+            file,
+            size,
+            opened_at: SystemTime::now()
+        })
+    }
+
+    /**
+     * Append a line, rotating the file first if it is due.
+     *
+     * # Arguments
+     * * `line` - The line, including its newline.
+     */
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()>{
+        if self.due(line.len() as u64){
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn due(&self, additional: u64) -> bool{
+        // a single line larger than the limit is still written, to a fresh file
+        let too_large = self.max_size.is_some_and(|max_size| self.size > 0 && self.size + additional > max_size);
+        let too_old = self.interval.is_some_and(|interval| {
+            self.opened_at.elapsed().map(|elapsed| elapsed >= interval).unwrap_or(false)
+        });
+        too_large || too_old
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()>{
+        let rotated = |index: usize| PathBuf::from(format!("{}.{index}", self.path.display()));
+        if self.keep == 0{
+            std::fs::remove_file(&self.path)?;
+        }else{
+            // path.(keep-1) overwrites path.keep, and so on down to path itself
+            for index in (1..self.keep).rev(){
+                match std::fs::rename(rotated(index), rotated(index + 1)){
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn read(path: &Path) -> Option<String>{
+        std::fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn rotation_shifts_and_caps_the_files(){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let rotated = |index: usize| directory.path().join(format!("access.log.{index}"));
+        // each line is 6 bytes, so every line after the first rotates the file
+        let mut file = RotatingFile::open(&path, Some(10), None, 2).unwrap();
+        for line in ["line1\n", "line2\n", "line3\n", "line4\n"]{
+            file.write_line(line).unwrap();
+        }
+        assert_eq!(read(&path).as_deref(), Some("line4\n"));
+        assert_eq!(read(&rotated(1)).as_deref(), Some("line3\n"));
+        assert_eq!(read(&rotated(2)).as_deref(), Some("line2\n"));
+        // line1 was in the oldest file, which was dropped
+        assert_eq!(read(&rotated(3)), None);
+    }
+
+    #[test]
+    fn lines_fill_the_file_up_to_the_limit(){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let mut file = RotatingFile::open(&path, Some(12), None, 1).unwrap();
+        file.write_line("line1\n").unwrap();
+        file.write_line("line2\n").unwrap();
+        assert_eq!(read(&path).as_deref(), Some("line1\nline2\n"));
+        // a line larger than the limit still goes to a file of its own
+        file.write_line("a much longer line\n").unwrap();
+        assert_eq!(read(&path).as_deref(), Some("a much longer line\n"));
+        assert_eq!(read(&directory.path().join("access.log.1")).as_deref(), Some("line1\nline2\n"));
+    }
+
+    #[test]
+    fn existing_file_counts_towards_the_limit(){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        std::fs::write(&path, "old line\n").unwrap();
+        let mut file = RotatingFile::open(&path, Some(12), None, 1).unwrap();
+        file.write_line("line1\n").unwrap();
+        assert_eq!(read(&path).as_deref(), Some("line1\n"));
+        assert_eq!(read(&directory.path().join("access.log.1")).as_deref(), Some("old line\n"));
+    }
+
+    #[test]
+    fn keeping_no_files_starts_over(){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let mut file = RotatingFile::open(&path, Some(6), None, 0).unwrap();
+        file.write_line("line1\n").unwrap();
+        file.write_line("line2\n").unwrap();
+        assert_eq!(read(&path).as_deref(), Some("line2\n"));
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn old_files_are_rotated(){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let mut file = RotatingFile::open(&path, None, Some(Duration::from_secs(3600)), 1).unwrap();
+        file.write_line("line1\n").unwrap();
+        file.opened_at -= Duration::from_secs(3600);
+        file.write_line("line2\n").unwrap();
+        assert_eq!(read(&path).as_deref(), Some("line2\n"));
+        assert_eq!(read(&directory.path().join("access.log.1")).as_deref(), Some("line1\n"));
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::net::IpAddr;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::server_core::ftp::{hash_password, valid_username};
+use crate::server_utils::{Config, ServerMode};
+
+/**
+ * The command line of the server binary.
+ */
+#[derive(Parser)]
+#[command(name = "WebServer", version, about = "An HTTP and FTP server", long_about = None)]
+pub struct Cli{
+    // serve with the defaults when no command is given
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Subcommand)]
+pub enum Command{
+    /// Start the servers
+    Serve(ServeArgs),
+    /// Check a configuration file, printing every problem found
+    CheckConfig(ConfigArgs),
+    /// Hash a password read from standard input, for an FTP user
+    HashPassword(HashPasswordArgs),
+    /// Print build information
+    Version
+}
+
+#[derive(Args, Default)]
+pub struct ConfigArgs{
+    /// The configuration file [default: $WEBSERVER_CONFIG, or config.yaml]
+    #[arg(short, long)]
+    pub config: Option<PathBuf>
+}
+
+impl ConfigArgs{
+    /**
+     * The path of the configuration file, from the flag if it is given.
+     */
+    pub fn path(&self) -> PathBuf{
+        self.config.clone().unwrap_or_else(Config::default_path)
+    }
+}
+
+#[derive(Args, Default)]
+pub struct ServeArgs{
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// The address every listener binds to, instead of listen.address of the configuration. Its
+    /// family replaces listen.family, unless an IPv6 address is given for a dual-stack listener
+    #[arg(short, long)]
+    pub bind: Option<IpAddr>,
+    /// Do not start the HTTP and HTTPS listeners
+    #[arg(long)]
+    pub no_http: bool,
+    /// Do not start the FTP listener
+    #[arg(long)]
+    pub no_ftp: bool
+}
+
+impl ServeArgs{
+    /**
+     * The servers to start.
+     */
+    pub fn modes(&self) -> Vec<ServerMode>{
+        let mut modes = Vec::new();
+        if !self.no_http{
+            modes.push(ServerMode::HTTP);
+        }
+        if !self.no_ftp{
+            modes.push(ServerMode::FTP);
+        }
+        modes
+    }
+}
+
+#[derive(Args)]
+pub struct HashPasswordArgs{
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Write the hash to the password file of this user, in the ftp_users_directory of the configuration
+    #[arg(short, long)]
+    pub user: Option<String>
+}
+
+/**
+ * Load the configuration, printing every problem if it is invalid.
+ *
+ * # Arguments
+ * * `path` - The path of the configuration file.
+ */
+pub fn load_config(path: &Path) -> Result<Config, ()>{
+    Config::load(path).map_err(|e| eprintln!("{e}"))
+}
+
+/**
+ * Check a configuration file, returning whether it is valid.
+ *
+ * # Arguments
+ * * `args` - The configuration file to check.
+ */
+pub fn check_config(args: &ConfigArgs) -> bool{
+    let path = args.path();
+    match load_config(&path){
+        Ok(config) => {
+            for warning in &config.warnings{
+                println!("warning: {warning}");
+            }
+            println!("Config file {} is valid", path.display());
+            true
+        },
+        Err(()) => false
+    }
+}
+
+/**
+ * Hash a password read from standard input, and print it or write it to a user's password file.
+ *
+ * The password is not taken as an argument, so that it is not left in the shell history, and is
+ * not echoed when typed at a terminal (on platforms other than Unix, it is). The password file
+ * is only readable by its owner.
+ *
+ * # Arguments
+ * * `args` - The user to provision, if any.
+ */
+pub fn hash_password_command(args: &HashPasswordArgs) -> Result<(), String>{
+    let stdin = std::io::stdin();
+    let mut password = String::new();
+    {
+        // restores echo when dropped, however the password is read
+        let _echo_off = if stdin.is_terminal(){
+            eprint!("Password: ");
+            let _ = std::io::stderr().flush();
+            EchoOff::new(&stdin)
+        }else{
+            None
+        };
+        stdin.lock().read_line(&mut password).map_err(|e| format!("Could not read the password: {e}"))?;
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty(){
+        return Err("The password is empty".to_string());
+    }
+    let hash = hash_password(password);
+
+    let Some(user) = &args.user else {
+        println!("{hash}");
+        return Ok(());
+    };
+    if !valid_username(user){
+        return Err(format!("{user} is not a valid username"));
+    }
+    let config = load_config(&args.config.path()).map_err(|_| "Could not load the configuration".to_string())?;
+    let directory = Path::new(&config.ftp_users_directory);
+    std::fs::create_dir_all(directory).map_err(|e| format!("Could not create {}: {e}", directory.display()))?;
+    let file = directory.join(format!("{user}.passwd"));
+    write_private(&file, &format!("{hash}\n")).map_err(|e| format!("Could not write {}: {e}", file.display()))?;
+    println!("Wrote the password of {user} to {}", file.display());
+    Ok(())
+}
+
+/**
+ * Write a file which only its owner may read, replacing any earlier one.
+ *
+ * # Arguments
+ * * `path` - The file to write.
+ * * `contents` - What to write to it.
+ */
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()>{
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // the mode only applies when the file is created, so an existing file is tightened too
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+/**
+ * Turns off echo on a terminal until dropped, so that a password typed at it is not shown.
+ */
+#[cfg(unix)]
+struct EchoOff{
+    fd: std::os::fd::RawFd,
+    original: libc::termios
+}
+
+#[cfg(unix)]
+impl EchoOff{
+    /**
+     * # Returns
+     * None if the terminal settings could not be changed, in which case echo stays on.
+     */
+    fn new(terminal: &impl std::os::fd::AsRawFd) -> Option<Self>{
+        let fd = terminal.as_raw_fd();
+        let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills in the termios when it succeeds, and only then is it read
+        let original = unsafe {
+            if libc::tcgetattr(fd, original.as_mut_ptr()) != 0{
+                return None;
+            }
+            original.assume_init()
+        };
+        let mut silent = original;
+        // hide what is typed, but still end the prompt's line when enter is pressed
+        silent.c_lflag &= !libc::ECHO;
+        silent.c_lflag |= libc::ECHONL;
+        // SAFETY: fd is an open terminal, and silent a valid termios
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) } != 0{
+            return None;
+        }
+        Some(Self{ fd, original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff{
+    fn drop(&mut self){
+        // SAFETY: the settings were read from this terminal, which is still open
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/**
+ * Echo cannot be turned off on this platform, so the password is shown as it is typed.
+ */
+#[cfg(not(unix))]
+struct EchoOff;
+
+#[cfg(not(unix))]
+impl EchoOff{
+    fn new<T>(_terminal: &T) -> Option<Self>{
+        None
+    }
+}
+
+/**
+ * Print the version of the server, and how it was built.
+ */
+pub fn version(){
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    println!("target: {}-{}", std::env::consts::ARCH, std::env::consts::OS);
+    println!("profile: {}", if cfg!(debug_assertions) {"debug"} else {"release"});
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn private_files_are_only_readable_by_their_owner(){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("alice.passwd");
+        write_private(&path, "first\n").unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // an existing file is replaced, and its permissions tightened
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, "second\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::server_utils::Config;
+
+/**
+ * Names of fields whose values are never logged.
+ */
+const SECRET_FIELDS: &[&str] = &["password", "pass", "secret", "token", "authorization"];
+
+/**
+ * Install the subscriber which writes diagnostics to standard error.
+ *
+ * The filter is taken from RUST_LOG if it is set, then from the log_filter of the configuration,
+ * and is "info" otherwise. Secrets are redacted as each field is formatted, so a careless event
+ * cannot leak a password: fields with secret names are hidden entirely, and the argument of an FTP
+ * PASS command is hidden wherever it appears.
+ *
+ * # Arguments
+ * * `config` - The server configuration.
+ */
+pub fn init(config: &Config){
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(config.log_filter.as_deref().unwrap_or("info")))
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid log filter, using \"info\": {e}");
+            EnvFilter::new("info")
+        });
+
+    let fields = tracing_subscriber::fmt::format::debug_fn(|writer, field, value| {
+        let name = field.name();
+        if SECRET_FIELDS.iter().any(|secret| name.eq_ignore_ascii_case(secret)){
+            return write!(writer, "{name}=***");
+        }
+        let mut rendered = String::new();
+        write!(rendered, "{value:?}")?;
+        let rendered = redact_pass_arguments(&rendered);
+        if name == "message"{
+            write!(writer, "{rendered}")
+        }else{
+            write!(writer, "{name}={rendered}")
+        }
+    }).delimited(" ");
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .fmt_fields(fields)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+/**
+ * Replace the argument of every PASS command in some text with "***".
+ *
+ * # Arguments
+ * * `text` - The text, such as a raw FTP command.
+ */
+fn redact_pass_arguments(text: &str) -> String{
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = find_pass_command(rest){
+        // keep "PASS " and drop the argument, up to the end of the line or the quoted value
+        let argument_start = index + 5;
+        redacted.push_str(&rest[..argument_start]);
+        redacted.push_str("***");
+        let argument = &rest[argument_start..];
+        let argument_end = argument.find(['\r', '\n', '"']).unwrap_or(argument.len());
+        rest = &argument[argument_end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/**
+ * Find "PASS " at the start of a word, in any case.
+ */
+fn find_pass_command(text: &str) -> Option<usize>{
+    let bytes = text.as_bytes();
+    (0..bytes.len().saturating_sub(4)).find(|&index| {
+        bytes[index..index + 5].eq_ignore_ascii_case(b"PASS ")
+            && (index == 0 || !bytes[index - 1].is_ascii_alphanumeric())
+    })
+}
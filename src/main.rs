@@ -1,17 +1,24 @@
-/**
+/*
  * Andrew Heschl
  * 
  * Server.
  */
 
-use std::{self, future::Future};
-use core::net::SocketAddr;
-use tokio::{self, net::{TcpListener, TcpStream}};
+use std::{future::Future, process::ExitCode, sync::Arc, time::Duration};
+use clap::Parser;
+
+use cli::{Cli, Command, ServeArgs};
+use server_utils::ServerMode;
 
+mod cli;
 mod shutdown_utils;
 mod server_core;
 mod router;
 mod server_utils;
+mod middleware;
+mod access_log;
+mod logging;
+mod metrics;
 
 fn spawn_with_hook(fut: impl Future + Send + 'static, tx: tokio::sync::oneshot::Sender<()>) {
     tokio::spawn(async move {
@@ -21,36 +28,180 @@ fn spawn_with_hook(fut: impl Future + Send + 'static, tx: tokio::sync::oneshot::
 }
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error>{
-    let config = server_utils::Config::new();
-    // connection system
-    let endpoint = SocketAddr::from(([127, 0, 0, 1], config.http_port));
-    let listener = TcpListener::bind(endpoint).await?;
-
-    // http server shutdown signal
-    let (tx_http, rx_http) = tokio::sync::oneshot::channel();
-    let http = server_core::start_server(
-        listener,
-        shutdown_utils::shutdown_on_ctrl_c(),
-        10,
-        server_core::http::connection_adaptor
-    );
-    spawn_with_hook(http, tx_http);
+async fn main() -> ExitCode{
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve(ServeArgs::default())){
+        Command::Serve(args) => match serve(args).await{
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::FAILURE
+            }
+        },
+        Command::CheckConfig(args) => if cli::check_config(&args) {ExitCode::SUCCESS} else {ExitCode::FAILURE},
+        Command::HashPassword(args) => match cli::hash_password_command(&args){
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::FAILURE
+            }
+        },
+        Command::Version => {
+            cli::version();
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+/**
+ * Start the servers, and run them until they have shut down after ctrl+c.
+ *
+ * # Arguments
+ * * `args` - The configuration file, address and servers to serve.
+ */
+async fn serve(args: ServeArgs) -> Result<(), std::io::Error>{
+    let started_at = std::time::Instant::now();
+    let mut config = match cli::load_config(&args.config.path()){
+        Ok(config) => config,
+        Err(()) => return Err(std::io::Error::other("Could not load the configuration"))
+    };
+    if let Some(bind) = args.bind{
+        config.listen.bind_to(bind);
+    }
+    let config = Arc::new(config);
+    let modes = args.modes();
+    if modes.is_empty(){
+        return Err(std::io::Error::other("--no-http and --no-ftp leave nothing to serve"));
+    }
+    logging::init(&config);
+    for warning in &config.warnings{
+        tracing::warn!("{warning}");
+    }
+    let access_log = match &config.access_log{
+        Some(access_log) => Some(Arc::new(access_log::AccessLog::from_config(access_log)?)),
+        None => None
+    };
+    let metrics = Arc::new(metrics::Metrics::new());
+    let http_state = Arc::new(server_core::http::HttpState{
+        config: Arc::clone(&config),
+        mime_types: server_core::mime::MimeRegistry::from_config(&config)?,
+        middleware: middleware::from_config(&config.middleware),
+        tls: config.tls.as_ref()
+            .map(|tls| server_core::tls::acceptor(tls, server_core::http::alpn_protocols(&config.http2)))
+            .transpose()?,
+        protocols: server_core::http::connection_builder(&config.http2),
+        access_log: access_log.clone(),
+        metrics: Arc::clone(&metrics)
+    });
+    let ftp_state = Arc::new(server_core::ftp::FtpState{
+        config: Arc::clone(&config),
+        access_log,
+        metrics: Arc::clone(&metrics),
+        commands: server_core::ftp::standard_commands()
+    });
+    // signalled as each listener finishes shutting down
+    let mut finished = Vec::new();
+    if modes.contains(&ServerMode::HTTP){
+        let listener = server_core::bind_listener(&config.listen, config.http_port)?;
+
+        let (tx_http, rx_http) = tokio::sync::oneshot::channel();
+        let http_connections = shutdown_utils::ShutdownHelper::new();
+        metrics.register_listener("http", http_connections.clone());
+        let http = server_core::start_server(
+            listener,
+            shutdown_utils::shutdown_on_ctrl_c(),
+            10,
+            config.connection_limits.clone(),
+            http_connections,
+            Arc::clone(&http_state),
+            server_core::http::connection_adaptor
+        );
+        spawn_with_hook(http, tx_http);
+        finished.push(rx_http);
+        // HTTPS, if configured
+        if let Some(tls) = &config.tls{
+            let tls_listener = server_core::bind_listener(&config.listen, tls.port)?;
+
+            let (tx_https, rx_https) = tokio::sync::oneshot::channel();
+            let https_connections = shutdown_utils::ShutdownHelper::new();
+            metrics.register_listener("https", https_connections.clone());
+            let https = server_core::start_server(
+                tls_listener,
+                shutdown_utils::shutdown_on_ctrl_c(),
+                10,
+                config.connection_limits.clone(),
+                https_connections,
+                Arc::clone(&http_state),
+                server_core::http::tls_connection_adaptor
+            );
+            spawn_with_hook(https, tx_https);
+            finished.push(rx_https);
+        }
+    }
     // Now FTP
-    let control_endpoint = SocketAddr::from(([127, 0, 0, 1], config.ftp_control_port));
-    let control_listener = TcpListener::bind(control_endpoint).await?;
-
-    let (tx_ftp, rx_ftp) = tokio::sync::oneshot::channel();
-    let fcp = server_core::start_server(
-        control_listener,
-        shutdown_utils::shutdown_on_ctrl_c(),
-        10,
-        server_core::ftp::connection_adaptor
-    );
-    spawn_with_hook(fcp, tx_ftp);
+    if modes.contains(&ServerMode::FTP){
+        let control_listener = server_core::bind_listener(&config.listen, config.ftp_control_port)?;
+
+        let (tx_ftp, rx_ftp) = tokio::sync::oneshot::channel();
+        let ftp_connections = shutdown_utils::ShutdownHelper::new();
+        metrics.register_listener("ftp", ftp_connections.clone());
+        let fcp = server_core::start_server(
+            control_listener,
+            shutdown_utils::shutdown_on_ctrl_c(),
+            10,
+            config.connection_limits.clone(),
+            ftp_connections,
+            ftp_state,
+            server_core::ftp::connection_adaptor
+        );
+        spawn_with_hook(fcp, tx_ftp);
+        finished.push(rx_ftp);
+    }
+    // health checks and metrics, if configured
+    // the admin listener outlives the others, so that it can report them draining
+    let (tx_drained, rx_drained) = tokio::sync::oneshot::channel::<()>();
+    let rx_admin = match &config.admin{
+        Some(admin) => {
+            let admin_listener = server_core::bind_listener(&config.listen, admin.port)?;
+            let mut admin_protocols = http_state.protocols.clone();
+            // admin connections have no ConnectionActivity, so hyper limits reading their headers,
+            // along with the wait between requests
+            admin_protocols.http1().header_read_timeout(Duration::from_secs(config.connections.header_read_timeout));
+            let admin_state = Arc::new(server_core::admin::AdminState{
+                metrics: Arc::clone(&metrics),
+                protocols: admin_protocols,
+                started_at,
+                config_hash: config.hash.clone()
+            });
+
+            let (tx_admin, rx_admin) = tokio::sync::oneshot::channel();
+            let admin_connections = shutdown_utils::ShutdownHelper::new();
+            metrics.register_listener("admin", admin_connections.clone());
+            let admin = server_core::start_server(
+                admin_listener,
+                async { let _ = rx_drained.await; },
+                10,
+                config.connection_limits.clone(),
+                admin_connections,
+                admin_state,
+                server_core::admin::connection_adaptor
+            );
+            spawn_with_hook(admin, tx_admin);
+            Some(rx_admin)
+        },
+        None => None
+    };
 
     // wait for shutdown signal
-    rx_http.await.unwrap();
-    rx_ftp.await.unwrap();
+    for rx in finished{
+        rx.await.unwrap();
+    }
+    let _ = tx_drained.send(());
+    if let Some(rx_admin) = rx_admin{
+        rx_admin.await.unwrap();
+    }
+    if let Some(access_log) = &http_state.access_log{
+        access_log.flush();
+    }
     Ok(())
 }
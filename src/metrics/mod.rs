@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::shutdown_utils::ShutdownHelper;
+
+/**
+ * Upper bounds, in seconds, of the buckets of the latency histograms.
+ */
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/**
+ * HTTP methods which are reported by name. Anything else is reported as "OTHER", so a client
+ * cannot create an unbounded number of series.
+ */
+const KNOWN_METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "CONNECT", "TRACE"];
+
+/**
+ * A latency histogram with cumulative buckets, as Prometheus expects.
+ */
+struct Histogram{
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64
+}
+
+impl Histogram{
+    fn new() -> Self{
+        Self{
+            buckets: LATENCY_BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0)
+        }
+    }
+
+    fn observe(&self, duration: Duration){
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS){
+            if seconds <= *bound{
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str){
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS){
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {}", bucket.load(Ordering::Relaxed));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {}", self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/**
+ * A counter split by a set of label values.
+ */
+struct LabeledCounter<const N: usize>{
+    values: Mutex<BTreeMap<[String; N], u64>>
+}
+
+impl<const N: usize> LabeledCounter<N>{
+    fn new() -> Self{
+        Self{ values: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn increment(&self, labels: [String; N]){
+        *self.values.lock().unwrap().entry(labels).or_insert(0) += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str, label_names: [&str; N]){
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        for (labels, value) in self.values.lock().unwrap().iter(){
+            let labels: Vec<String> = label_names.iter().zip(labels)
+                .map(|(label_name, label)| format!("{label_name}=\"{}\"", escape_label(label)))
+                .collect();
+            let _ = writeln!(out, "{name}{{{}}} {value}", labels.join(","));
+        }
+    }
+}
+
+/**
+ * Counters and histograms describing both servers, rendered in the Prometheus text format.
+ */
+pub struct Metrics{
+    listeners: Mutex<Vec<(String, ShutdownHelper)>>,
+    http_requests: LabeledCounter<2>,
+    http_request_duration: Histogram,
+    http_bytes_sent: AtomicU64,
+    http_bytes_received: AtomicU64,
+    ftp_commands: LabeledCounter<2>,
+    ftp_command_duration: Histogram,
+    ftp_transfers: LabeledCounter<2>,
+    ftp_bytes_sent: AtomicU64,
+    ftp_bytes_received: AtomicU64,
+    ftp_login_failures: AtomicU64
+}
+
+impl Metrics{
+    pub fn new() -> Self{
+        Self{
+            listeners: Mutex::new(Vec::new()),
+            http_requests: LabeledCounter::new(),
+            http_request_duration: Histogram::new(),
+            http_bytes_sent: AtomicU64::new(0),
+            http_bytes_received: AtomicU64::new(0),
+            ftp_commands: LabeledCounter::new(),
+            ftp_command_duration: Histogram::new(),
+            ftp_transfers: LabeledCounter::new(),
+            ftp_bytes_sent: AtomicU64::new(0),
+            ftp_bytes_received: AtomicU64::new(0),
+            ftp_login_failures: AtomicU64::new(0)
+        }
+    }
+
+    /**
+     * Report the open connections of a listener.
+     *
+     * # Arguments
+     * * `name` - The name of the listener, such as "http".
+     * * `shutdown_helper` - The helper which the listener registers its connections with.
+     */
+    pub fn register_listener(&self, name: &str, shutdown_helper: ShutdownHelper){
+        self.listeners.lock().unwrap().push((name.to_string(), shutdown_helper));
+    }
+
+    /**
+     * The registered listeners, by name.
+     */
+    pub fn listeners(&self) -> Vec<(String, ShutdownHelper)>{
+        self.listeners.lock().unwrap().clone()
+    }
+
+    /**
+     * Record an HTTP request once its response has been sent.
+     *
+     * # Arguments
+     * * `method` - The method of the request.
+     * * `status` - The status of the response.
+     * * `bytes_sent` - The bytes of the response body which were sent.
+     * * `duration` - The time from receiving the request to finishing the response.
+     */
+    pub fn record_http_request(&self, method: &str, status: u16, bytes_sent: u64, duration: Duration){
+        let method = if KNOWN_METHODS.contains(&method) {method} else {"OTHER"};
+        self.http_requests.increment([method.to_string(), status.to_string()]);
+        self.http_request_duration.observe(duration);
+        self.http_bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+    }
+
+    /**
+     * Record bytes of HTTP request bodies as they are received.
+     */
+    pub fn record_http_bytes_received(&self, bytes: u64){
+        self.http_bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /**
+     * Record an FTP command once it has been replied to.
+     *
+     * # Arguments
+     * * `verb` - The command, already limited to the known verbs.
+     * * `reply_code` - The code of the reply.
+     * * `duration` - The time taken to handle the command.
+     */
+    pub fn record_ftp_command(&self, verb: &str, reply_code: u16, duration: Duration){
+        self.ftp_commands.increment([verb.to_string(), reply_code.to_string()]);
+        self.ftp_command_duration.observe(duration);
+    }
+
+    /**
+     * Record an FTP data transfer.
+     *
+     * # Arguments
+     * * `direction` - "download", "upload" or "listing".
+     * * `succeeded` - Whether the transfer completed.
+     */
+    pub fn record_ftp_transfer(&self, direction: &str, succeeded: bool){
+        let result = if succeeded {"success"} else {"failure"};
+        self.ftp_transfers.increment([direction.to_string(), result.to_string()]);
+    }
+
+    pub fn record_ftp_bytes_sent(&self, bytes: u64){
+        self.ftp_bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_ftp_bytes_received(&self, bytes: u64){
+        self.ftp_bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_ftp_login_failure(&self){
+        self.ftp_login_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Render every metric in the Prometheus text exposition format.
+     */
+    pub fn render(&self) -> String{
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP webserver_active_connections Connections currently open.\n# TYPE webserver_active_connections gauge");
+        for (name, shutdown_helper) in self.listeners.lock().unwrap().iter(){
+            let _ = writeln!(out, "webserver_active_connections{{listener=\"{}\"}} {}", escape_label(name), shutdown_helper.active());
+        }
+
+        self.http_requests.render(&mut out, "webserver_http_requests_total", "HTTP requests served.", ["method", "status"]);
+        self.http_request_duration.render(&mut out, "webserver_http_request_duration_seconds", "Time taken to serve HTTP requests.");
+        render_counter(&mut out, "webserver_http_sent_bytes_total", "Bytes of HTTP response bodies sent.", &self.http_bytes_sent);
+        render_counter(&mut out, "webserver_http_received_bytes_total", "Bytes of HTTP request bodies received.", &self.http_bytes_received);
+
+        self.ftp_commands.render(&mut out, "webserver_ftp_commands_total", "FTP commands handled.", ["verb", "reply_code"]);
+        self.ftp_command_duration.render(&mut out, "webserver_ftp_command_duration_seconds", "Time taken to handle FTP commands.");
+        self.ftp_transfers.render(&mut out, "webserver_ftp_transfers_total", "FTP data transfers.", ["direction", "result"]);
+        render_counter(&mut out, "webserver_ftp_sent_bytes_total", "Bytes sent over FTP data connections.", &self.ftp_bytes_sent);
+        render_counter(&mut out, "webserver_ftp_received_bytes_total", "Bytes received over FTP data connections.", &self.ftp_bytes_received);
+        render_counter(&mut out, "webserver_ftp_login_failures_total", "Failed FTP logins.", &self.ftp_login_failures);
+        out
+    }
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, value: &AtomicU64){
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}", value.load(Ordering::Relaxed));
+}
+
+fn escape_label(value: &str) -> String{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use http_body_util::combinators::BoxBody;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{body::Bytes, Request, Response, StatusCode};
+
+use crate::middleware::Middleware;
+use crate::router::RequestBody;
+use crate::server_core::full_box_body;
+
+/**
+ * Adds a fixed set of headers to every response, such as security headers or Cache-Control.
+ * 
+ * Headers already set by the handler are replaced.
+ */
+pub struct ResponseHeaders{
+    headers: Vec<(HeaderName, HeaderValue)>
+}
+
+impl ResponseHeaders{
+    /**
+     * Create the middleware. The headers are checked when the configuration is loaded, so any
+     * whose name or value is still not valid are skipped.
+     * 
+     * # Arguments
+     * * `headers` - The names and values of the headers to add.
+     */
+    pub fn new(headers: &[(String, String)]) -> Self{
+        let headers = headers.iter()
+            .filter_map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+                let value = HeaderValue::from_str(value).ok()?;
+                Some((name, value))
+            })
+            .collect();
+        Self{ headers }
+    }
+}
+
+impl Middleware for ResponseHeaders{
+    fn after(&self, response: &mut Response<BoxBody<Bytes, std::io::Error>>){
+        for (name, value) in &self.headers{
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/**
+ * Requires HTTP Basic authentication for every request.
+ * 
+ * Passwords are stored as sha256 hashes, the same way as FTP passwords.
+ */
+pub struct BasicAuth{
+    realm: String,
+    users: HashMap<String, String>
+}
+
+impl BasicAuth{
+    /**
+     * Create the middleware.
+     * 
+     * # Arguments
+     * * `realm` - The realm sent to the client in WWW-Authenticate.
+     * * `users` - Usernames, and the sha256 hashes of their passwords.
+     */
+    pub fn new(realm: &str, users: HashMap<String, String>) -> Self{
+        Self{
+            realm: realm.to_string(),
+            users
+        }
+    }
+
+    fn is_authorized(&self, authorization: &str) -> bool{
+        let encoded = match authorization.strip_prefix("Basic "){
+            Some(encoded) => encoded.trim(),
+            None => return false
+        };
+        let decoded = match base64::engine::general_purpose::STANDARD.decode(encoded){
+            Ok(decoded) => String::from_utf8_lossy(&decoded).to_string(),
+            Err(_) => return false
+        };
+        match decoded.split_once(':'){
+            Some((username, password)) => self.users.get(username)
+                .map(|hash| hash.eq_ignore_ascii_case(&sha256::digest(password)))
+                .unwrap_or(false),
+            None => false
+        }
+    }
+}
+
+impl Middleware for BasicAuth{
+    fn before(&self, request: &mut Request<RequestBody>) -> Option<Response<BoxBody<Bytes, std::io::Error>>>{
+        let authorized = request.headers()
+            .get("Authorization")
+            .and_then(|authorization| authorization.to_str().ok())
+            .map(|authorization| self.is_authorized(authorization))
+            .unwrap_or(false);
+        if authorized{
+            return None;
+        }
+        let response = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", format!("Basic realm=\"{}\"", self.realm))
+            .body(full_box_body("Unauthorized"))
+            .unwrap();
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<RequestBody>{
+        let mut request = Request::builder().uri("/");
+        if let Some(authorization) = authorization{
+            request = request.header("Authorization", authorization);
+        }
+        request.body(full_box_body("")).unwrap()
+    }
+
+    fn basic(credentials: &str) -> String{
+        format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+    }
+
+    fn basic_auth() -> BasicAuth{
+        BasicAuth::new("Staff", HashMap::from([("alice".to_string(), sha256::digest("s3cret").to_uppercase())]))
+    }
+
+    #[test]
+    fn headers_are_added_and_replace_the_handlers(){
+        let middleware = ResponseHeaders::new(&[
+            ("X-Frame-Options".to_string(), "DENY".to_string()),
+            ("Cache-Control".to_string(), "no-store".to_string())
+        ]);
+        let mut response = Response::builder()
+            .header("Cache-Control", "max-age=60")
+            .header("Content-Type", "text/plain")
+            .body(full_box_body(""))
+            .unwrap();
+        middleware.after(&mut response);
+        assert_eq!(response.headers()["X-Frame-Options"], "DENY");
+        assert_eq!(response.headers().get_all("Cache-Control").iter().collect::<Vec<_>>(), ["no-store"]);
+        assert_eq!(response.headers()["Content-Type"], "text/plain");
+    }
+
+    #[test]
+    fn headers_which_are_not_valid_are_skipped(){
+        let middleware = ResponseHeaders::new(&[
+            ("Bad Name".to_string(), "x".to_string()),
+            ("X-Bad-Value".to_string(), "a\nb".to_string()),
+            ("X-Good".to_string(), "yes".to_string())
+        ]);
+        let mut response = Response::new(full_box_body(""));
+        middleware.after(&mut response);
+        assert_eq!(response.headers().len(), 1);
+        assert_eq!(response.headers()["X-Good"], "yes");
+    }
+
+    #[test]
+    fn basic_auth_lets_known_users_through(){
+        let middleware = basic_auth();
+        assert!(middleware.before(&mut request(Some(&basic("alice:s3cret")))).is_none());
+        // passwords may contain colons
+        let colon = BasicAuth::new("Staff", HashMap::from([("bob".to_string(), sha256::digest("a:b"))]));
+        assert!(colon.before(&mut request(Some(&basic("bob:a:b")))).is_none());
+    }
+
+    #[test]
+    fn basic_auth_challenges_everyone_else(){
+        let middleware = basic_auth();
+        for authorization in [
+            None,
+            Some(basic("alice:wrong")),
+            Some(basic("mallory:s3cret")),
+            Some(basic("alice")),
+            Some("Basic not-base64!".to_string()),
+            Some("Bearer token".to_string())
+        ]{
+            let response = middleware.before(&mut request(authorization.as_deref()));
+            let response = response.unwrap_or_else(|| panic!("{authorization:?} was let through"));
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()["WWW-Authenticate"], "Basic realm=\"Staff\"");
+        }
+    }
+}
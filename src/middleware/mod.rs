@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{body::Bytes, Request, Response};
+use tower::{Layer, Service};
+
+use crate::router::{HandlerFuture, RequestBody};
+use crate::server_utils::MiddlewareConfig;
+
+pub mod builtin;
+
+/**
+ * Behaviour which runs around every HTTP request, such as authentication or extra headers.
+ * 
+ * Both hooks default to doing nothing, so a middleware only implements the ones it needs.
+ */
+pub trait Middleware: Send + Sync{
+    /**
+     * Called before the request is routed.
+     * 
+     * Returning a response short-circuits the request: the router, and any inner middleware, are
+     * skipped, but the after hooks of outer middleware still run.
+     * 
+     * # Arguments
+     * * `request` - The request, which may be modified.
+     */
+    fn before(&self, _request: &mut Request<RequestBody>) -> Option<Response<BoxBody<Bytes, std::io::Error>>>{
+        None
+    }
+
+    /**
+     * Called with the response on its way back to the client.
+     * 
+     * # Arguments
+     * * `response` - The response, which may be modified.
+     */
+    fn after(&self, _response: &mut Response<BoxBody<Bytes, std::io::Error>>){}
+}
+
+/**
+ * A tower Layer which wraps a service in a Middleware.
+ */
+#[derive(Clone)]
+pub struct MiddlewareLayer{
+    middleware: Arc<dyn Middleware>
+}
+
+impl MiddlewareLayer{
+    pub fn new(middleware: Arc<dyn Middleware>) -> Self{
+        Self{ middleware }
+    }
+}
+
+impl<S> Layer<S> for MiddlewareLayer{
+    type Service = MiddlewareService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service{
+        MiddlewareService{
+            inner,
+            middleware: Arc::clone(&self.middleware)
+        }
+    }
+}
+
+/**
+ * The tower Service produced by MiddlewareLayer.
+ */
+#[derive(Clone)]
+pub struct MiddlewareService<S>{
+    inner: S,
+    middleware: Arc<dyn Middleware>
+}
+
+impl<S> Service<Request<RequestBody>> for MiddlewareService<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<BoxBody<Bytes, std::io::Error>>, Error = hyper::Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody<Bytes, std::io::Error>>;
+    type Error = hyper::Error;
+    type Future = HandlerFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>{
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<RequestBody>) -> Self::Future{
+        if let Some(response) = self.middleware.before(&mut request){
+            return Box::pin(async { Ok(response) });
+        }
+        let middleware = Arc::clone(&self.middleware);
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = response.await?;
+            middleware.after(&mut response);
+            Ok(response)
+        })
+    }
+}
+
+/**
+ * Create the middleware described by the server configuration, outermost first.
+ * 
+ * # Arguments
+ * * `configs` - The middleware section of the configuration.
+ */
+pub fn from_config(configs: &[MiddlewareConfig]) -> Vec<Arc<dyn Middleware>>{
+    configs.iter().map(|config| -> Arc<dyn Middleware> {
+        match config{
+            MiddlewareConfig::Headers(headers) => Arc::new(builtin::ResponseHeaders::new(headers)),
+            MiddlewareConfig::BasicAuth{ realm, users } => Arc::new(builtin::BasicAuth::new(realm, users.clone()))
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use hyper::StatusCode;
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+    use crate::server_core::full_box_body;
+
+    /**
+     * Refuses every request whose path is /private, and marks every response it sees.
+     */
+    struct Guard;
+
+    impl Middleware for Guard{
+        fn before(&self, request: &mut Request<RequestBody>) -> Option<Response<BoxBody<Bytes, std::io::Error>>>{
+            if request.uri().path() != "/private"{
+                return None;
+            }
+            let mut response = Response::new(full_box_body("refused"));
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            Some(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn short_circuit_skips_inner_layers_but_not_outer_after_hooks(){
+        let reached = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let reached = Arc::clone(&reached);
+            service_fn(move |_request: Request<RequestBody>| {
+                reached.store(true, Ordering::SeqCst);
+                async { Ok::<_, hyper::Error>(Response::new(full_box_body("handled"))) }
+            })
+        };
+        let headers = Arc::new(builtin::ResponseHeaders::new(&[("X-Seen".to_string(), "yes".to_string())]));
+        let service = MiddlewareLayer::new(headers).layer(MiddlewareLayer::new(Arc::new(Guard)).layer(handler));
+
+        let request = |path: &str| Request::builder().uri(path).body(full_box_body("")).unwrap();
+        let response = service.clone().oneshot(request("/private")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["X-Seen"], "yes");
+        assert!(!reached.load(Ordering::SeqCst));
+
+        let response = service.oneshot(request("/public")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["X-Seen"], "yes");
+        assert!(reached.load(Ordering::SeqCst));
+    }
+}
@@ -1,129 +1,590 @@
+use std::collections::HashMap;
 use std::future::Future;
-use http_body_util::combinators::BoxBody;
-use hyper::{body::Bytes, Method, Request, Response};
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty};
+use hyper::{body::Bytes, Method, Request, Response, StatusCode};
 use hyper::Error;
-// TODO simplify the types to allow for reuse
-// NOT IN USE
+use crate::server_core::full_box_body;
+use crate::server_utils::paths::percent_decode;
+
 /**
- * Create a service that routes requests to different handlers based on the request method.
- * 
+ * The result produced by every request handler.
+ */
+pub type HandlerResult = Result<Response<BoxBody<Bytes, std::io::Error>>, Error>;
+
+/**
+ * The body of every request passed to middleware and handlers.
+ *
+ * The body hyper reads is wrapped, so that the time taken to receive it can be limited.
+ */
+pub type RequestBody = BoxBody<Bytes, std::io::Error>;
+
+/**
+ * The boxed future returned by a routed service.
+ */
+pub type HandlerFuture = BoxFuture<'static, HandlerResult>;
+
+/**
+ * The handlers of a resource, one for each request method it supports.
+ *
+ * Every handler shares one type, so to mix different functions, wrap them in closures which box
+ * their futures.
+ */
+pub struct MethodHandlers<F>{
+    pub get: Option<F>,
+    pub post: Option<F>,
+    pub put: Option<F>,
+    pub delete: Option<F>,
+    pub patch: Option<F>,
+    pub head: Option<F>,
+    pub connect: Option<F>,
+    pub options: Option<F>,
+    pub trace: Option<F>
+}
+
+impl<F> Default for MethodHandlers<F>{
+    fn default() -> Self{
+        Self{
+            get: None,
+            post: None,
+            put: None,
+            delete: None,
+            patch: None,
+            head: None,
+            connect: None,
+            options: None,
+            trace: None
+        }
+    }
+}
+
+impl<F> MethodHandlers<F>{
+    /**
+     * Find the handler registered for a method.
+     */
+    fn handler(&self, method: &Method) -> Option<&F>{
+        match *method {
+            Method::GET => self.get.as_ref(),
+            Method::POST => self.post.as_ref(),
+            Method::PUT => self.put.as_ref(),
+            Method::DELETE => self.delete.as_ref(),
+            Method::PATCH => self.patch.as_ref(),
+            Method::HEAD => self.head.as_ref(),
+            Method::CONNECT => self.connect.as_ref(),
+            Method::OPTIONS => self.options.as_ref(),
+            Method::TRACE => self.trace.as_ref(),
+            _ => None
+        }
+    }
+
+    /**
+     * The slot holding the handler for a method, or None for methods which cannot be routed.
+     */
+    fn slot(&mut self, method: &Method) -> Option<&mut Option<F>>{
+        match *method {
+            Method::GET => Some(&mut self.get),
+            Method::POST => Some(&mut self.post),
+            Method::PUT => Some(&mut self.put),
+            Method::DELETE => Some(&mut self.delete),
+            Method::PATCH => Some(&mut self.patch),
+            Method::HEAD => Some(&mut self.head),
+            Method::CONNECT => Some(&mut self.connect),
+            Method::OPTIONS => Some(&mut self.options),
+            Method::TRACE => Some(&mut self.trace),
+            _ => None
+        }
+    }
+
+    /**
+     * Take every registered handler, along with its method.
+     */
+    #[allow(dead_code)]
+    fn into_registered(self) -> Vec<(Method, F)>{
+        [
+            (Method::GET, self.get),
+            (Method::POST, self.post),
+            (Method::PUT, self.put),
+            (Method::DELETE, self.delete),
+            (Method::PATCH, self.patch),
+            (Method::HEAD, self.head),
+            (Method::CONNECT, self.connect),
+            (Method::OPTIONS, self.options),
+            (Method::TRACE, self.trace)
+        ].into_iter()
+            .filter_map(|(method, handler)| handler.map(|handler| (method, handler)))
+            .collect()
+    }
+
+    /**
+     * The value of the Allow header for this resource.
+     *
+     * HEAD is allowed wherever GET is, and OPTIONS is always allowed.
+     */
+    fn allow(&self) -> String{
+        let methods = [
+            (Method::GET, self.get.is_some()),
+            (Method::HEAD, self.head.is_some() || self.get.is_some()),
+            (Method::POST, self.post.is_some()),
+            (Method::PUT, self.put.is_some()),
+            (Method::DELETE, self.delete.is_some()),
+            (Method::PATCH, self.patch.is_some()),
+            (Method::CONNECT, self.connect.is_some()),
+            (Method::OPTIONS, true),
+            (Method::TRACE, self.trace.is_some())
+        ];
+        methods.iter()
+            .filter(|(_, allowed)| *allowed)
+            .map(|(method, _)| method.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+}
+
+/**
+ * Answer a request with the handler registered for its method.
+ *
+ * Methods without a handler are answered here:
+ * * HEAD runs the GET handler, and sends its headers without the body.
+ * * OPTIONS replies 204 with the Allow header.
+ * * Other standard methods reply 405 with the Allow header.
+ * * Methods this server does not know reply 501.
+ *
  * # Arguments
- * * `not_implemented` - The handler to call when the request method is not implemented.
- * * `get` - The handler to call when the request method is GET.
- * * `post` - The handler to call when the request method is POST.
- * * `put` - The handler to call when the request method is PUT.
- * * `delete` - The handler to call when the request method is DELETE.
- * * `patch` - The handler to call when the request method is PATCH.
- * * `head` - The handler to call when the request method is HEAD.
- * * `connect` - The handler to call when the request method is CONNECT.
- * * `options` - The handler to call when the request method is OPTIONS.
- * * `trace` - The handler to call when the request method is TRACE.
- * * `other` - The handler to call when the request method is not one of the above.
+ * * `handlers` - The handler to call for each request method.
+ * * `request` - The request to answer.
  */
-pub fn routed_service<F, Fut>(
-    not_implemented: F,
-    get: Option<F>,
-    post: Option<F>,
-    put: Option<F>,
-    delete: Option<F>,
-    patch: Option<F>,
-    head: Option<F>,
-    connect: Option<F>,
-    options: Option<F>,
-    trace: Option<F>
-) -> impl Fn(Request<hyper::body::Incoming>) -> Fut + Copy + Send + Sync + 'static
+async fn dispatch<F, Fut>(handlers: &MethodHandlers<F>, request: Request<RequestBody>) -> HandlerResult
 where
-    F: Copy + Fn(Request<hyper::body::Incoming>) -> Fut + Send + Sync + 'static,
-    Fut: Future<Output = Result<Response<BoxBody<Bytes, Error>>, Error>> + Send + 'static,
+    F: Fn(Request<RequestBody>) -> Fut,
+    Fut: Future<Output = HandlerResult>,
 {
-    fn inner<F, Fut>(
-        not_implemented: F,
-        get: Option<F>,
-        post: Option<F>,
-        put: Option<F>,
-        delete: Option<F>,
-        patch: Option<F>,
-        head: Option<F>,
-        connect: Option<F>,
-        options: Option<F>,
-        trace: Option<F>,
-        request: Request<hyper::body::Incoming>,
-    ) -> Fut
-    where
-        F: Copy + Fn(Request<hyper::body::Incoming>) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = Result<Response<BoxBody<Bytes, Error>>, Error>> + Send + 'static,
-    {
-        match request.method() {
-            &Method::GET => {
-                if let Some(f) = get {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
-                }
-            }
-            &Method::POST => {
-                if let Some(f) = post {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
-                }
-            }
-            &Method::PUT => {
-                if let Some(f) = put {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
-                }
-            }
-            &Method::DELETE => {
-                if let Some(f) = delete {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
+    let method = request.method().clone();
+    if let Some(handler) = handlers.handler(&method) {
+        return handler(request).await;
+    }
+    match method {
+        Method::HEAD if handlers.get.is_some() => {
+            let get = handlers.get.as_ref().unwrap();
+            let response = get(request).await?;
+            Ok(without_body(response))
+        }
+        Method::OPTIONS => Ok(empty_response(StatusCode::NO_CONTENT, &handlers.allow())),
+        Method::GET | Method::HEAD | Method::POST | Method::PUT | Method::DELETE
+            | Method::PATCH | Method::CONNECT | Method::TRACE => {
+            Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED, &handlers.allow()))
+        }
+        _ => Ok(empty_response(StatusCode::NOT_IMPLEMENTED, &handlers.allow()))
+    }
+}
+
+/**
+ * A segment of a route pattern.
+ */
+#[derive(Clone, PartialEq)]
+enum Segment{
+    // must equal the path segment
+    Static(String),
+    // ":name", matches any one segment
+    Param(String),
+    // "*name", matches the rest of the path, and must be last
+    Wildcard(String)
+}
+
+impl Segment{
+    /**
+     * How specific the segment is. When several routes match, the most specific wins.
+     */
+    fn rank(&self) -> u8{
+        match self{
+            Segment::Static(_) => 2,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 0
+        }
+    }
+}
+
+/**
+ * Split a route pattern, such as "/api/users/:id", into segments.
+ */
+fn parse_pattern(pattern: &str) -> Vec<Segment>{
+    let parts: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+    parts.iter().enumerate().map(|(index, part)| {
+        if let Some(name) = part.strip_prefix(':') {
+            Segment::Param(name.to_string())
+        } else if let Some(name) = part.strip_prefix('*') {
+            assert!(index == parts.len() - 1, "Wildcard must be the last segment of {pattern}");
+            Segment::Wildcard(name.to_string())
+        } else {
+            Segment::Static(part.to_string())
+        }
+    }).collect()
+}
+
+/**
+ * Parameters captured from the request path by the matched route.
+ *
+ * Inserted into the extensions of the request before its handler is called, and read back with
+ * `request.extensions().get::<PathParams>()`.
+ */
+#[derive(Clone, Default)]
+pub struct PathParams{
+    // percent decoded values, by the name they were given in the route pattern
+    pub params: HashMap<String, String>
+}
+
+/**
+ * A request handler in a box, so that different closures can be registered in one Router.
+ */
+pub type BoxedHandler = Box<dyn Fn(Request<RequestBody>) -> HandlerFuture + Send + Sync>;
+
+struct Route{
+    segments: Vec<Segment>,
+    handlers: MethodHandlers<BoxedHandler>
+}
+
+impl Route{
+    /**
+     * Match a request path against the route, capturing its parameters.
+     */
+    fn matches(&self, path: &[&str]) -> Option<PathParams>{
+        let mut params = PathParams::default();
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(expected) => {
+                    if path.get(index) != Some(&expected.as_str()) {
+                        return None;
+                    }
                 }
-            }
-            &Method::PATCH => {
-                if let Some(f) = patch {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
+                Segment::Param(name) => {
+                    let value = path.get(index)?;
+                    params.params.insert(name.clone(), percent_decode(value)?);
                 }
-            }
-            &Method::HEAD => {
-                if let Some(f) = head {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
+                Segment::Wildcard(name) => {
+                    let rest = path[index.min(path.len())..].join("/");
+                    params.params.insert(name.clone(), percent_decode(&rest)?);
+                    return Some(params);
                 }
             }
-            &Method::CONNECT => {
-                if let Some(f) = connect {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
-                }
+        }
+        if path.len() == self.segments.len() {Some(params)} else {None}
+    }
+
+    /**
+     * How specific the route is for a path it matches, compared between the routes matching it.
+     *
+     * Each path segment is ranked by the route segment which matched it, so the route with the
+     * more specific segment where they first differ wins. A wildcard ranks every segment it
+     * captured, and when it captured none, the route which ends there wins over it.
+     *
+     * # Arguments
+     * * `path_length` - The number of segments in the matched path.
+     */
+    fn specificity(&self, path_length: usize) -> (Vec<u8>, bool){
+        let wildcard_rank = Segment::Wildcard(String::new()).rank();
+        let ranks = (0..path_length)
+            .map(|index| self.segments.get(index).map_or(wildcard_rank, Segment::rank))
+            .collect();
+        let ends_without_wildcard = !matches!(self.segments.last(), Some(Segment::Wildcard(_)));
+        (ranks, ends_without_wildcard)
+    }
+}
+
+/**
+ * Routes requests to handlers by their path and method.
+ *
+ * # Example
+ * ```
+ * let router = Router::new()
+ *     .get("/api/users/:id", get_user)
+ *     .delete("/api/users/:id", delete_user)
+ *     .mount("/admin", admin_router)
+ *     .get("/static/:file", static_files);
+ * let service = service_fn(router.into_service());
+ * ```
+ */
+pub struct Router{
+    routes: Vec<Route>
+}
+
+impl Router{
+    /**
+     * Create a Router with no routes.
+     */
+    pub fn new() -> Self{
+        Self{
+            routes: Vec::new()
+        }
+    }
+
+    /**
+     * Register a handler for a method and path pattern.
+     *
+     * Patterns are made of "/" separated segments. A segment may be a literal, ":name" to capture
+     * one segment, or "*name" as the last segment to capture the rest of the path. Registering
+     * the same method and pattern again replaces the handler.
+     *
+     * # Arguments
+     * * `method` - The request method to handle.
+     * * `pattern` - The path pattern to handle.
+     * * `handler` - The function called with matching requests.
+     */
+    pub fn route<H, Fut>(self, method: Method, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let handler: BoxedHandler = Box::new(move |request| Box::pin(handler(request)));
+        self.insert(method, parse_pattern(pattern), handler)
+    }
+
+    pub fn get<H, Fut>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    #[allow(dead_code)]
+    pub fn post<H, Fut>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    #[allow(dead_code)]
+    pub fn put<H, Fut>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    #[allow(dead_code)]
+    pub fn delete<H, Fut>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /**
+     * Register every route of another Router below a path prefix.
+     *
+     * The prefix is a pattern like any other, so it may capture parameters too.
+     *
+     * # Arguments
+     * * `prefix` - The path the routes are mounted at, such as "/api".
+     * * `router` - The routes to mount.
+     */
+    #[allow(dead_code)]
+    pub fn mount(mut self, prefix: &str, router: Router) -> Self{
+        let prefix = parse_pattern(prefix);
+        for route in router.routes {
+            let mut segments = prefix.clone();
+            segments.extend(route.segments);
+            for (method, handler) in route.handlers.into_registered() {
+                self = self.insert(method, segments.clone(), handler);
             }
-            &Method::OPTIONS => {
-                if let Some(f) = options {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
-                }
+        }
+        self
+    }
+
+    fn insert(mut self, method: Method, segments: Vec<Segment>, handler: BoxedHandler) -> Self{
+        let index = match self.routes.iter().position(|route| route.segments == segments) {
+            Some(index) => index,
+            None => {
+                self.routes.push(Route{ segments, handlers: MethodHandlers::default() });
+                self.routes.len() - 1
             }
-            &Method::TRACE => {
-                if let Some(f) = trace {
-                    return f(request);
-                } else {
-                    return not_implemented(request);
-                }
+        };
+        match self.routes[index].handlers.slot(&method) {
+            Some(slot) => *slot = Some(handler),
+            None => panic!("Cannot route the {method} method")
+        }
+        self
+    }
+
+    /**
+     * Answer a request with the most specific matching route.
+     *
+     * The captured PathParams are inserted into the request's extensions. Requests which match
+     * no route get a 404.
+     *
+     * # Arguments
+     * * `request` - The request to answer.
+     */
+    pub async fn handle(&self, mut request: Request<RequestBody>) -> HandlerResult{
+        let path: Vec<&str> = request.uri().path().split('/').filter(|part| !part.is_empty()).collect();
+        let matched = self.routes.iter()
+            .filter_map(|route| route.matches(&path).map(|params| (route, params)))
+            .max_by_key(|(route, _)| route.specificity(path.len()));
+
+        match matched {
+            Some((route, params)) => {
+                request.extensions_mut().insert(params);
+                dispatch(&route.handlers, request).await
             }
-            _ => not_implemented(request)
+            None => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(full_box_body("Not found"))
+                .unwrap())
+        }
+    }
+
+    /**
+     * Turn the Router into a service function which can be handed to hyper.
+     */
+    pub fn into_service(self) -> impl Fn(Request<RequestBody>) -> HandlerFuture + Clone + Send + Sync + 'static{
+        let router = Arc::new(self);
+        move |request: Request<RequestBody>| {
+            let router = Arc::clone(&router);
+            Box::pin(async move { router.handle(request).await })
         }
     }
+}
+
+impl Default for Router{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+/**
+ * Drop the body of a response, keeping its headers, including Content-Length.
+ */
+fn without_body(response: Response<BoxBody<Bytes, std::io::Error>>) -> Response<BoxBody<Bytes, std::io::Error>>{
+    let (parts, _) = response.into_parts();
+    Response::from_parts(parts, empty_body())
+}
+
+fn empty_response(status: StatusCode, allow: &str) -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(status)
+        .header("Allow", allow)
+        .body(empty_body())
+        .unwrap()
+}
+
+fn empty_body() -> BoxBody<Bytes, std::io::Error>{
+    Empty::new()
+        .map_err(|n| match n {})
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn named(name: &'static str) -> impl Fn(Request<RequestBody>) -> HandlerFuture + Send + Sync + 'static{
+        move |_| Box::pin(async move { Ok(Response::new(full_box_body(name))) })
+    }
+
+    fn echo_param(name: &'static str) -> impl Fn(Request<RequestBody>) -> HandlerFuture + Send + Sync + 'static{
+        move |request| Box::pin(async move {
+            let value = request.extensions().get::<PathParams>().and_then(|params| params.params.get(name).cloned());
+            Ok(Response::new(full_box_body(value.unwrap_or_default())))
+        })
+    }
+
+    async fn call(router: &Router, method: Method, path: &str) -> (StatusCode, String){
+        let request = Request::builder().method(method).uri(path).body(empty_body()).unwrap();
+        let response = router.handle(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn get(router: &Router, path: &str) -> String{
+        call(router, Method::GET, path).await.1
+    }
+
+    #[tokio::test]
+    async fn static_beats_param_beats_wildcard(){
+        let router = Router::new()
+            .get("/a/*rest", named("wildcard"))
+            .get("/a/:id", named("param"))
+            .get("/a/b", named("static"));
+        assert_eq!(get(&router, "/a/b").await, "static");
+        assert_eq!(get(&router, "/a/c").await, "param");
+        assert_eq!(get(&router, "/a/c/d").await, "wildcard");
+    }
+
+    #[tokio::test]
+    async fn route_ending_at_the_path_beats_empty_wildcard(){
+        let router = Router::new()
+            .get("/*path", named("root wildcard"))
+            .get("/", named("root"))
+            .get("/a/*rest", named("wildcard"))
+            .get("/a", named("exact"));
+        assert_eq!(get(&router, "/").await, "root");
+        assert_eq!(get(&router, "/a").await, "exact");
+        assert_eq!(get(&router, "/a/").await, "exact");
+        assert_eq!(get(&router, "/a/b").await, "wildcard");
+        assert_eq!(get(&router, "/b").await, "root wildcard");
+    }
+
+    #[tokio::test]
+    async fn earlier_static_segment_wins(){
+        let router = Router::new()
+            .get("/:x/b", named("param first"))
+            .get("/a/:y", named("static first"));
+        assert_eq!(get(&router, "/a/b").await, "static first");
+        assert_eq!(get(&router, "/c/b").await, "param first");
+    }
+
+    #[tokio::test]
+    async fn captures_decoded_params(){
+        let router = Router::new()
+            .get("/users/:id", echo_param("id"))
+            .get("/files/*path", echo_param("path"));
+        assert_eq!(get(&router, "/users/a%20b").await, "a b");
+        assert_eq!(get(&router, "/files/x/y.txt").await, "x/y.txt");
+        assert_eq!(get(&router, "/files").await, "");
+    }
+
+    #[tokio::test]
+    async fn unmatched_paths_and_methods(){
+        let router = Router::new()
+            .get("/a", named("get"))
+            .post("/a", named("post"));
+        assert_eq!(call(&router, Method::GET, "/b").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(call(&router, Method::POST, "/a").await.1, "post");
+        assert_eq!(call(&router, Method::PUT, "/a").await.0, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(call(&router, Method::HEAD, "/a").await, (StatusCode::OK, String::new()));
+    }
+
+    #[tokio::test]
+    async fn registering_again_replaces_the_handler(){
+        let router = Router::new()
+            .get("/a/:id", named("first"))
+            .get("/a/:id", named("second"));
+        assert_eq!(get(&router, "/a/1").await, "second");
+    }
 
-    move |request: Request<hyper::body::Incoming>| {
-        inner(
-            not_implemented, get, post, put, delete, patch, head, connect, options, trace, request,
-        )
+    #[tokio::test]
+    async fn mounted_routes_are_below_the_prefix(){
+        let admin = Router::new()
+            .get("/", named("admin index"))
+            .get("/users/:id", echo_param("id"))
+            .delete("/users/:id", named("deleted"));
+        let router = Router::new()
+            .get("/users/:id", named("public user"))
+            .put("/admin/users/:id", named("put"))
+            .mount("/admin", admin)
+            .mount("/sites/:site", Router::new().get("/files/*path", echo_param("site")));
+        assert_eq!(get(&router, "/admin").await, "admin index");
+        assert_eq!(get(&router, "/admin/users/7").await, "7");
+        assert_eq!(get(&router, "/users/7").await, "public user");
+        assert_eq!(call(&router, Method::DELETE, "/admin/users/7").await.1, "deleted");
+        // mounting adds to a route registered at the same path, rather than replacing it
+        assert_eq!(call(&router, Method::PUT, "/admin/users/7").await.1, "put");
+        assert_eq!(get(&router, "/sites/docs/files/a/b").await, "docs");
+        assert_eq!(call(&router, Method::GET, "/users").await.0, StatusCode::NOT_FOUND);
     }
-}
\ No newline at end of file
+}
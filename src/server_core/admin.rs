@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use tokio::net::TcpStream;
+use tracing::Instrument;
+
+use crate::metrics::Metrics;
+use crate::router::{HandlerFuture, HandlerResult, RequestBody, Router};
+use crate::server_core::connection_info::ConnectionInfo;
+use crate::server_core::full_box_body;
+use crate::shutdown_utils::ShutdownHelper;
+
+/**
+ * State shared by every connection to the admin listener.
+ */
+pub struct AdminState{
+    pub metrics: Arc<Metrics>,
+    pub protocols: auto::Builder<TokioExecutor>,
+    pub started_at: Instant,
+    // sha256 of the configuration file the server was started with
+    pub config_hash: String
+}
+
+fn text_response(status: StatusCode, body: &'static str) -> HandlerResult{
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(full_box_body(body))
+        .unwrap())
+}
+
+/**
+ * Whether any listener has seen the shutdown signal.
+ */
+fn draining(state: &AdminState) -> bool{
+    state.metrics.listeners().iter().any(|(_, shutdown_helper)| shutdown_helper.draining())
+}
+
+async fn health_handler() -> HandlerResult{
+    text_response(StatusCode::OK, "ok")
+}
+
+async fn ready_handler(state: Arc<AdminState>) -> HandlerResult{
+    if draining(&state){
+        text_response(StatusCode::SERVICE_UNAVAILABLE, "draining")
+    }else{
+        text_response(StatusCode::OK, "ready")
+    }
+}
+
+async fn status_handler(state: Arc<AdminState>) -> HandlerResult{
+    let listeners: serde_json::Map<String, serde_json::Value> = state.metrics.listeners().into_iter()
+        .map(|(name, shutdown_helper)| (name, serde_json::json!({
+            "active_connections": shutdown_helper.active(),
+            "draining": shutdown_helper.draining()
+        })))
+        .collect();
+    let status = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_seconds": state.started_at.elapsed().as_secs(),
+        "config_hash": state.config_hash,
+        "draining": draining(&state),
+        "listeners": listeners
+    });
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(full_box_body(status.to_string()))
+        .unwrap())
+}
+
+async fn metrics_handler(state: Arc<AdminState>) -> HandlerResult{
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+        .body(full_box_body(state.metrics.render()))
+        .unwrap())
+}
+
+/**
+ * Build the service which routes each admin request to its handler.
+ *
+ * /healthz answers whenever the process is serving, /readyz fails with 503 once any listener has
+ * seen the shutdown signal, /status describes the server as JSON, and /metrics is in the
+ * Prometheus text format.
+ *
+ * # Arguments
+ * * `state` - State shared by every admin connection.
+ */
+pub fn router(state: Arc<AdminState>) -> impl Fn(Request<RequestBody>) -> HandlerFuture + Clone{
+    let (ready_state, status_state) = (Arc::clone(&state), Arc::clone(&state));
+    Router::new()
+        .get("/healthz", |_| health_handler())
+        .get("/readyz", move |_| ready_handler(Arc::clone(&ready_state)))
+        .get("/status", move |_| status_handler(Arc::clone(&status_state)))
+        .get("/metrics", move |_| metrics_handler(Arc::clone(&state)))
+        .into_service()
+}
+
+/**
+ * Serve the admin endpoints on a connection.
+ *
+ * Admin requests are not counted in the HTTP metrics or written to the access log, so that
+ * scraping the metrics does not change them.
+ *
+ * # Arguments
+ * * `stream` - The connection.
+ * * `info` - What is known about the connection.
+ * * `shutdown_helper` - Tracks the connection, so shutdown can wait for it.
+ * * `state` - State shared by every admin connection.
+ */
+pub fn connection_adaptor(stream: TcpStream, info: ConnectionInfo, shutdown_helper: &mut ShutdownHelper, state: Arc<AdminState>){
+    let handle = shutdown_helper.register(info.peer_addr.ip());
+    let span = tracing::info_span!("admin_connection", id = info.id, peer = %info.peer_addr);
+    tokio::spawn(async move {
+        let router = router(Arc::clone(&state));
+        let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+            router(request.map(|body| body.map_err(std::io::Error::other).boxed()))
+        });
+        if let Err(e) = state.protocols.serve_connection(TokioIo::new(stream), service).await{
+            tracing::debug!(error = %e, "error serving admin connection");
+        }
+        handle.send(()).unwrap();
+    }.instrument(span));
+}
@@ -0,0 +1,104 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use tokio::net::TcpStream;
+
+/**
+ * Ids are unique across every listener of the process.
+ */
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/**
+ * What is known about a connection when it is accepted.
+ *
+ * HTTP handlers find it in the extensions of each request, and FTP sessions keep it for their
+ * lifetime.
+ */
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo{
+    pub id: u64,
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    #[allow(dead_code)]
+    pub accepted_at: SystemTime,
+    // set once the TLS handshake completes, on HTTPS connections
+    pub tls: Option<TlsInfo>
+}
+
+/**
+ * The parameters negotiated by the TLS handshake of a connection.
+ */
+#[derive(Clone, Debug)]
+pub struct TlsInfo{
+    // the server name the client asked for with SNI
+    pub server_name: Option<String>,
+    // the protocol chosen with ALPN, such as "h2"
+    pub alpn_protocol: Option<String>,
+    pub protocol_version: String,
+    pub cipher_suite: String
+}
+
+fn canonical(address: SocketAddr) -> SocketAddr{
+    SocketAddr::new(address.ip().to_canonical(), address.port())
+}
+
+impl ConnectionInfo{
+    /**
+     * Describe a connection which has just been accepted, giving it the next id.
+     *
+     * IPv4 connections to a dual-stack listener are described with their IPv4 addresses, rather
+     * than as IPv4-mapped IPv6 addresses, so that they are logged and limited like any other.
+     *
+     * # Arguments
+     * * `stream` - The connection.
+     * * `peer_addr` - The address of the remote end, as returned by accept.
+     */
+    pub fn accepted(stream: &TcpStream, peer_addr: SocketAddr) -> std::io::Result<Self>{
+        Ok(Self{
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr: canonical(peer_addr),
+            local_addr: canonical(stream.local_addr()?),
+            accepted_at: SystemTime::now(),
+            tls: None
+        })
+    }
+}
+
+impl TlsInfo{
+    /**
+     * Read the negotiated parameters of a connection whose handshake has completed.
+     *
+     * # Arguments
+     * * `connection` - The server side of the TLS connection.
+     */
+    pub fn from_connection(connection: &rustls::ServerConnection) -> Self{
+        Self{
+            server_name: connection.server_name().map(|name| name.to_string()),
+            alpn_protocol: connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+            protocol_version: connection.protocol_version()
+                .map(|version| format!("{version:?}"))
+                .unwrap_or_default(),
+            cipher_suite: connection.negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite()))
+                .unwrap_or_default()
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionInfo{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result{
+        write!(f, "#{} {} -> {}", self.id, self.peer_addr, self.local_addr)?;
+        if let Some(tls) = &self.tls{
+            write!(f, " ({} {}", tls.protocol_version, tls.cipher_suite)?;
+            if let Some(server_name) = &tls.server_name{
+                write!(f, ", {server_name}")?;
+            }
+            if let Some(alpn_protocol) = &tls.alpn_protocol{
+                write!(f, ", {alpn_protocol}")?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::fs::File;
+use tokio_util::bytes::{BufMut, BytesMut};
+use tokio_util::io::poll_read_buf;
+
+/**
+ * The largest chunk which is read from disk, and held in memory, at a time.
+ */
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/**
+ * A response body which streams a file from disk in fixed size chunks.
+ * 
+ * Only one chunk is read at a time, and the next is not read until hyper polls for it, so
+ * memory use is bounded by CHUNK_SIZE no matter how large the file is.
+ */
+pub struct FileBody{
+    file: File,
+    remaining: u64,
+    // reused for every chunk, only the bytes read are split off and sent
+    buffer: BytesMut
+}
+
+impl FileBody{
+    /**
+     * Create a new FileBody.
+     * 
+     * # Arguments
+     * * `file` - The file to stream, positioned at the first byte to send.
+     * * `length` - The number of bytes to send from the file.
+     */
+    pub fn new(file: File, length: u64) -> Self{
+        Self{
+            file,
+            remaining: length,
+            buffer: BytesMut::new()
+        }
+    }
+}
+
+impl Body for FileBody{
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>>{
+        if self.remaining == 0{
+            return Poll::Ready(None);
+        }
+        let this = &mut *self;
+        let chunk_size = this.remaining.min(CHUNK_SIZE as u64) as usize;
+        // reclaims the space of chunks hyper has already sent, rather than allocating again
+        this.buffer.reserve(chunk_size);
+
+        match poll_read_buf(Pin::new(&mut this.file), cx, &mut (&mut this.buffer).limit(chunk_size)){
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(Ok(0)) => {
+                // the file was truncated after Content-Length was sent
+                this.remaining = 0;
+                Poll::Ready(Some(Err(std::io::ErrorKind::UnexpectedEof.into())))
+            },
+            Poll::Ready(Ok(bytes_read)) => {
+                this.remaining -= bytes_read as u64;
+                Poll::Ready(Some(Ok(Frame::data(this.buffer.split().freeze()))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool{
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint{
+        SizeHint::with_exact(self.remaining)
+    }
+}
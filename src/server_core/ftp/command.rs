@@ -0,0 +1,481 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+use super::status::{TransferMode, TransferStructure, TransferType};
+
+/**
+ * The longest command line accepted, including its line ending.
+ */
+pub const MAX_LINE_LENGTH: usize = 4096;
+
+/**
+ * A network protocol of EPRT and EPSV, RFC 2428.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum NetworkProtocol{
+    Ipv4,
+    Ipv6
+}
+
+impl NetworkProtocol{
+    /**
+     * The network protocol used to reach an address.
+     */
+    pub fn of(address: IpAddr) -> Self{
+        if address.to_canonical().is_ipv4() {NetworkProtocol::Ipv4} else {NetworkProtocol::Ipv6}
+    }
+
+    /**
+     * The number naming the protocol in EPRT and EPSV.
+     */
+    pub fn number(self) -> u8{
+        match self{
+            NetworkProtocol::Ipv4 => 1,
+            NetworkProtocol::Ipv6 => 2
+        }
+    }
+}
+
+/**
+ * A command of the control connection, with its arguments parsed.
+ */
+pub enum FtpCommand{
+    User(String),
+    Pass(String),
+    Quit,
+    // active mode, to the address given by the client
+    Port(SocketAddr),
+    Eprt(SocketAddr),
+    Pasv,
+    // extended passive mode, optionally naming the protocol of the control connection
+    Epsv(Option<NetworkProtocol>),
+    // from now on, only EPSV may set up data connections
+    EpsvAll,
+    Type(TransferType),
+    Mode(TransferMode),
+    Stru(TransferStructure),
+    Retr(String),
+    Stor(String),
+    Cwd(String),
+    Cdup,
+    Pwd,
+    // the directory to list, or the current directory
+    List(Option<String>),
+    Noop,
+    Feat,
+    // the command to describe, in upper case, or None to list every command
+    Help(Option<String>)
+}
+
+/**
+ * Why a command line could not be parsed. Each one is answered, and the connection carries on.
+ */
+pub enum CommandError{
+    // longer than MAX_LINE_LENGTH
+    LineTooLong,
+    // empty, or not starting with a command name
+    Unrecognized,
+    // a command the server does not implement
+    NotImplemented,
+    // missing or malformed arguments
+    Syntax,
+    // a well formed parameter which the server does not support
+    UnsupportedParameter,
+    // EPRT or EPSV naming a network protocol other than 1 and 2
+    UnsupportedProtocol
+}
+
+impl CommandError{
+    /**
+     * The reply to a command which could not be parsed.
+     */
+    pub fn reply(&self) -> String{
+        match self{
+            CommandError::LineTooLong => "500 Command line too long.",
+            CommandError::Unrecognized => "500 Syntax error, command unrecognized.",
+            CommandError::NotImplemented => "502 This service not implemented.",
+            CommandError::Syntax => "501 Syntax error in parameters or arguments.",
+            CommandError::UnsupportedParameter => "504 Command not implemented for that parameter.",
+            CommandError::UnsupportedProtocol => "522 Network protocol not supported, use (1,2)."
+        }.to_string()
+    }
+}
+
+/**
+ * A line received on the control connection.
+ */
+pub struct CommandLine{
+    // the command name in upper case, empty if the line has none
+    pub verb: String,
+    // the line without its line ending, empty if it was too long
+    pub line: String,
+    pub command: Result<FtpCommand, CommandError>
+}
+
+impl CommandLine{
+    /**
+     * Parse a line of the control connection.
+     *
+     * Command names are case insensitive. The argument is everything after the first space, so it
+     * may itself contain spaces, such as a path.
+     *
+     * # Arguments
+     * * `line` - The line, without its line ending.
+     */
+    pub fn parse(line: &str) -> Self{
+        let (verb, argument) = match line.split_once(' '){
+            Some((verb, argument)) => (verb, Some(argument).filter(|argument| !argument.is_empty())),
+            None => (line, None)
+        };
+        let verb = verb.to_ascii_uppercase();
+        let command = if verb.is_empty() || !verb.chars().all(|c| c.is_ascii_alphabetic()){
+            Err(CommandError::Unrecognized)
+        }else{
+            parse_command(&verb, argument)
+        };
+        CommandLine{ verb, line: line.to_string(), command }
+    }
+
+    fn too_long() -> Self{
+        CommandLine{ verb: String::new(), line: String::new(), command: Err(CommandError::LineTooLong) }
+    }
+
+    /**
+     * The form of the line written to logs, which never includes a password.
+     */
+    pub fn loggable(&self) -> String{
+        if self.verb == "PASS"{
+            "PASS ***".to_string()
+        }else{
+            self.line.clone()
+        }
+    }
+}
+
+fn parse_command(verb: &str, argument: Option<&str>) -> Result<FtpCommand, CommandError>{
+    let required = || argument.map(str::to_string).ok_or(CommandError::Syntax);
+    let no_argument = |command| if argument.is_none() {Ok(command)} else {Err(CommandError::Syntax)};
+    match verb{
+        "USER" => required().map(FtpCommand::User),
+        "PASS" => Ok(FtpCommand::Pass(argument.unwrap_or_default().to_string())),
+        "QUIT" => no_argument(FtpCommand::Quit),
+        "PORT" => argument.and_then(parse_port_argument).map(FtpCommand::Port).ok_or(CommandError::Syntax),
+        "EPRT" => parse_eprt_argument(argument.ok_or(CommandError::Syntax)?).map(FtpCommand::Eprt),
+        "PASV" => no_argument(FtpCommand::Pasv),
+        "EPSV" => match argument{
+            None => Ok(FtpCommand::Epsv(None)),
+            Some(argument) if argument.eq_ignore_ascii_case("ALL") => Ok(FtpCommand::EpsvAll),
+            Some(argument) => parse_network_protocol(argument).map(|protocol| FtpCommand::Epsv(Some(protocol)))
+        },
+        "TYPE" => parse_type(argument.ok_or(CommandError::Syntax)?).map(FtpCommand::Type),
+        "MODE" => match parse_code(argument)?{
+            'S' => Ok(FtpCommand::Mode(TransferMode::Stream)),
+            'B' => Ok(FtpCommand::Mode(TransferMode::Block)),
+            'C' => Ok(FtpCommand::Mode(TransferMode::Compressed)),
+            _ => Err(CommandError::UnsupportedParameter)
+        },
+        "STRU" => match parse_code(argument)?{
+            'F' => Ok(FtpCommand::Stru(TransferStructure::File)),
+            'R' => Ok(FtpCommand::Stru(TransferStructure::Record)),
+            'P' => Ok(FtpCommand::Stru(TransferStructure::Page)),
+            _ => Err(CommandError::UnsupportedParameter)
+        },
+        "RETR" => required().map(FtpCommand::Retr),
+        "STOR" => required().map(FtpCommand::Stor),
+        "CWD" => required().map(FtpCommand::Cwd),
+        "CDUP" => no_argument(FtpCommand::Cdup),
+        "PWD" => no_argument(FtpCommand::Pwd),
+        "LIST" => Ok(FtpCommand::List(argument.map(str::to_string))),
+        "NOOP" => no_argument(FtpCommand::Noop),
+        "FEAT" => no_argument(FtpCommand::Feat),
+        "HELP" => Ok(FtpCommand::Help(argument.map(str::to_ascii_uppercase))),
+        _ => Err(CommandError::NotImplemented)
+    }
+}
+
+/**
+ * Parse the single letter argument of MODE and STRU.
+ */
+fn parse_code(argument: Option<&str>) -> Result<char, CommandError>{
+    let mut chars = argument.ok_or(CommandError::Syntax)?.chars();
+    match (chars.next(), chars.next()){
+        (Some(code), None) if code.is_ascii_alphabetic() => Ok(code.to_ascii_uppercase()),
+        _ => Err(CommandError::Syntax)
+    }
+}
+
+/**
+ * Parse the argument of TYPE, such as "A", "A N", "I" or "L 8".
+ */
+fn parse_type(argument: &str) -> Result<TransferType, CommandError>{
+    let argument = argument.to_ascii_uppercase();
+    match argument.split_whitespace().collect::<Vec<_>>()[..]{
+        ["A"] | ["A", "N"] => Ok(TransferType::Ascii),
+        ["E"] | ["E", "N"] => Ok(TransferType::EBCDIC),
+        ["I"] | ["L", "8"] => Ok(TransferType::Binary),
+        [_] | [_, _] => Err(CommandError::UnsupportedParameter),
+        _ => Err(CommandError::Syntax)
+    }
+}
+
+/**
+ * Parse the network protocol number of EPRT and EPSV.
+ */
+fn parse_network_protocol(argument: &str) -> Result<NetworkProtocol, CommandError>{
+    match argument.parse::<u8>(){
+        Ok(1) => Ok(NetworkProtocol::Ipv4),
+        Ok(2) => Ok(NetworkProtocol::Ipv6),
+        Ok(_) => Err(CommandError::UnsupportedProtocol),
+        Err(_) => Err(CommandError::Syntax)
+    }
+}
+
+/**
+ * Parse the argument of PORT, "h1,h2,h3,h4,p1,p2", into an IPv4 address and port.
+ */
+fn parse_port_argument(argument: &str) -> Option<SocketAddr>{
+    let numbers: Vec<u8> = argument.split(',').map(|number| number.trim().parse().ok()).collect::<Option<_>>()?;
+    let [a, b, c, d, high, low] = numbers[..] else {
+        return None;
+    };
+    Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), u16::from(high) << 8 | u16::from(low)))
+}
+
+/**
+ * Parse the argument of EPRT, such as "|1|132.235.1.2|6275|" or "|2|::1|5282|".
+ *
+ * The first character is the delimiter, and the network protocol is 1 for IPv4 and 2 for IPv6.
+ */
+fn parse_eprt_argument(argument: &str) -> Result<SocketAddr, CommandError>{
+    let delimiter = argument.chars().next().ok_or(CommandError::Syntax)?;
+    let fields: Vec<&str> = argument.split(delimiter).collect();
+    let ["", protocol, address, port, ""] = fields[..] else {
+        return Err(CommandError::Syntax);
+    };
+    let port: u16 = port.parse().map_err(|_| CommandError::Syntax)?;
+    let address = match parse_network_protocol(protocol)?{
+        NetworkProtocol::Ipv4 => IpAddr::V4(address.parse::<Ipv4Addr>().map_err(|_| CommandError::Syntax)?),
+        NetworkProtocol::Ipv6 => IpAddr::V6(address.parse::<Ipv6Addr>().map_err(|_| CommandError::Syntax)?)
+    };
+    Ok(SocketAddr::new(address, port))
+}
+
+/**
+ * Splits the control connection into command lines, ending in CRLF or a bare LF.
+ *
+ * A line longer than the maximum is dropped as it arrives, and yields a LineTooLong error once its
+ * end is reached, so that a client cannot make the server buffer without limit.
+ */
+pub struct FtpCodec{
+    max_line_length: usize,
+    // where to carry on looking for the end of the line
+    next_index: usize,
+    // inside a line which is too long
+    discarding: bool
+}
+
+impl FtpCodec{
+    /**
+     * # Arguments
+     * * `max_line_length` - The longest line accepted, including its line ending.
+     */
+    pub fn new(max_line_length: usize) -> Self{
+        Self{ max_line_length, next_index: 0, discarding: false }
+    }
+}
+
+impl Decoder for FtpCodec{
+    type Item = CommandLine;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<CommandLine>, std::io::Error>{
+        let newline = buffer[self.next_index..].iter()
+            .position(|byte| *byte == b'\n')
+            .map(|index| self.next_index + index);
+        match newline{
+            Some(end) => {
+                let line = buffer.split_to(end + 1);
+                self.next_index = 0;
+                if std::mem::take(&mut self.discarding) || line.len() > self.max_line_length{
+                    return Ok(Some(CommandLine::too_long()));
+                }
+                let line = String::from_utf8_lossy(&line[..end]);
+                Ok(Some(CommandLine::parse(line.strip_suffix('\r').unwrap_or(&line))))
+            },
+            None if self.discarding || buffer.len() > self.max_line_length => {
+                self.discarding = true;
+                self.next_index = 0;
+                buffer.clear();
+                Ok(None)
+            },
+            None => {
+                self.next_index = buffer.len();
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buffer: &mut BytesMut) -> Result<Option<CommandLine>, std::io::Error>{
+        // a last line without a line ending is dropped, the client is gone and cannot read the reply
+        match self.decode(buffer)?{
+            Some(line) => Ok(Some(line)),
+            None => {
+                buffer.clear();
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn decode_all(codec: &mut FtpCodec, buffer: &mut BytesMut) -> Vec<CommandLine>{
+        let mut lines = Vec::new();
+        while let Some(line) = codec.decode(buffer).unwrap(){
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn parse(line: &str) -> Result<FtpCommand, CommandError>{
+        CommandLine::parse(line).command
+    }
+
+    #[test]
+    fn command_split_across_reads(){
+        let mut codec = FtpCodec::new(MAX_LINE_LENGTH);
+        let mut buffer = BytesMut::from("US");
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(b"ER bob\r");
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(b"\n");
+        let line = codec.decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(line.verb, "USER");
+        assert!(matches!(line.command, Ok(FtpCommand::User(name)) if name == "bob"));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn pipelined_commands(){
+        let mut codec = FtpCodec::new(MAX_LINE_LENGTH);
+        let mut buffer = BytesMut::from("NOOP\r\nPWD\r\nCW");
+        let lines = decode_all(&mut codec, &mut buffer);
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(lines[0].command, Ok(FtpCommand::Noop)));
+        assert!(matches!(lines[1].command, Ok(FtpCommand::Pwd)));
+        assert_eq!(&buffer[..], b"CW");
+    }
+
+    #[test]
+    fn bare_line_feed_ends_a_line(){
+        let mut codec = FtpCodec::new(MAX_LINE_LENGTH);
+        let mut buffer = BytesMut::from("CWD docs\nPWD\n");
+        let lines = decode_all(&mut codec, &mut buffer);
+        assert!(matches!(&lines[0].command, Ok(FtpCommand::Cwd(path)) if path == "docs"));
+        assert!(matches!(lines[1].command, Ok(FtpCommand::Pwd)));
+    }
+
+    #[test]
+    fn over_long_line_is_dropped_and_the_next_one_read(){
+        let mut codec = FtpCodec::new(16);
+        let mut buffer = BytesMut::from("RETR aaaaaaaaaaaaaaaaaaaa");
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        // what was read of the long line is not kept
+        assert!(buffer.is_empty());
+        buffer.extend_from_slice(b"aaaa\r\nNOOP\r\n");
+        let lines = decode_all(&mut codec, &mut buffer);
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(lines[0].command, Err(CommandError::LineTooLong)));
+        assert_eq!(lines[0].command.as_ref().err().unwrap().reply(), "500 Command line too long.");
+        assert!(matches!(lines[1].command, Ok(FtpCommand::Noop)));
+    }
+
+    #[test]
+    fn over_long_line_within_one_read(){
+        let mut codec = FtpCodec::new(16);
+        let mut buffer = BytesMut::from(format!("STOR {}\r\nNOOP\r\n", "b".repeat(20)).as_str());
+        let lines = decode_all(&mut codec, &mut buffer);
+        assert!(matches!(lines[0].command, Err(CommandError::LineTooLong)));
+        assert!(matches!(lines[1].command, Ok(FtpCommand::Noop)));
+    }
+
+    #[test]
+    fn unfinished_last_line_is_dropped_at_end(){
+        let mut codec = FtpCodec::new(MAX_LINE_LENGTH);
+        let mut buffer = BytesMut::from("NOOP\r\nQUI");
+        assert!(matches!(codec.decode_eof(&mut buffer).unwrap().unwrap().command, Ok(FtpCommand::Noop)));
+        assert!(codec.decode_eof(&mut buffer).unwrap().is_none());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn verbs_are_case_insensitive(){
+        let line = CommandLine::parse("retr notes.txt");
+        assert_eq!(line.verb, "RETR");
+        assert!(matches!(line.command, Ok(FtpCommand::Retr(path)) if path == "notes.txt"));
+        assert!(matches!(parse("Epsv all"), Ok(FtpCommand::EpsvAll)));
+        assert!(matches!(parse("type i"), Ok(FtpCommand::Type(TransferType::Binary))));
+    }
+
+    #[test]
+    fn paths_keep_their_spaces(){
+        assert!(matches!(parse("RETR with space/a b.txt"), Ok(FtpCommand::Retr(path)) if path == "with space/a b.txt"));
+        assert!(matches!(parse("CWD  leading"), Ok(FtpCommand::Cwd(path)) if path == " leading"));
+        assert!(matches!(parse("RETR"), Err(CommandError::Syntax)));
+    }
+
+    #[test]
+    fn unknown_and_malformed_commands(){
+        assert!(matches!(parse(""), Err(CommandError::Unrecognized)));
+        assert!(matches!(parse("N0OP"), Err(CommandError::Unrecognized)));
+        assert!(matches!(parse("SITE CHMOD 777 x"), Err(CommandError::NotImplemented)));
+        assert!(matches!(parse("NOOP now"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("MODE X"), Err(CommandError::UnsupportedParameter)));
+        assert!(matches!(parse("TYPE X 1 2"), Err(CommandError::Syntax)));
+    }
+
+    #[test]
+    fn port_argument(){
+        assert!(matches!(parse("PORT 127,0,0,1,19,137"), Ok(FtpCommand::Port(address)) if address == "127.0.0.1:5001".parse().unwrap()));
+        assert!(matches!(parse("PORT 127,0,0,1,19"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("PORT 127,0,0,1,19,137,1"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("PORT 127,0,0,1,19,256"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("PORT 127.0.0.1.19.137"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("PORT"), Err(CommandError::Syntax)));
+    }
+
+    #[test]
+    fn eprt_argument(){
+        assert!(matches!(parse("EPRT |1|132.235.1.2|6275|"), Ok(FtpCommand::Eprt(address)) if address == "132.235.1.2:6275".parse().unwrap()));
+        assert!(matches!(parse("EPRT !2!::1!5282!"), Ok(FtpCommand::Eprt(address)) if address == "[::1]:5282".parse().unwrap()));
+        // wrong number of fields
+        assert!(matches!(parse("EPRT |1|132.235.1.2|6275"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("EPRT |1|132.235.1.2|6275|1|"), Err(CommandError::Syntax)));
+        // the delimiter is the first character, and must be used throughout
+        assert!(matches!(parse("EPRT |1!132.235.1.2|6275|"), Err(CommandError::Syntax)));
+        // the address must belong to the protocol
+        assert!(matches!(parse("EPRT |2|132.235.1.2|6275|"), Err(CommandError::Syntax)));
+        assert!(matches!(parse("EPRT |1|132.235.1.2|70000|"), Err(CommandError::Syntax)));
+        let unsupported = parse("EPRT |3|132.235.1.2|6275|");
+        assert!(matches!(unsupported, Err(CommandError::UnsupportedProtocol)));
+        assert!(unsupported.err().unwrap().reply().starts_with("522 "));
+    }
+
+    #[test]
+    fn epsv_argument(){
+        assert!(matches!(parse("EPSV"), Ok(FtpCommand::Epsv(None))));
+        assert!(matches!(parse("EPSV 2"), Ok(FtpCommand::Epsv(Some(NetworkProtocol::Ipv6)))));
+        assert!(matches!(parse("EPSV 3"), Err(CommandError::UnsupportedProtocol)));
+        assert!(matches!(parse("EPSV x"), Err(CommandError::Syntax)));
+    }
+
+    #[test]
+    fn passwords_are_not_logged(){
+        assert_eq!(CommandLine::parse("PASS hunter2").loggable(), "PASS ***");
+        assert_eq!(CommandLine::parse("USER bob").loggable(), "USER bob");
+    }
+}
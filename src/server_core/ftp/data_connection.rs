@@ -0,0 +1,97 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::server_utils::FtpPassiveConfig;
+
+/**
+ * Where the next search for a free passive port starts, so that consecutive transfers use
+ * different ports and a port is not reused while the client may still be connecting to it.
+ */
+static NEXT_PASSIVE_PORT: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * The data connection of an FTP session, used by the next transfer.
+ */
+pub enum DataConnection{
+    // opened by the server, to the address given with PORT
+    Active(TcpStream),
+    // waiting for the client to connect, after PASV or EPSV
+    Passive{
+        listener: TcpListener,
+        // only the client of the control connection may connect
+        peer: IpAddr,
+        accept_timeout: Duration
+    }
+}
+
+impl DataConnection{
+    /**
+     * Listen for a passive data connection on a free port of the configured range.
+     *
+     * # Arguments
+     * * `config` - The passive mode settings.
+     * * `local` - The local address of the control connection, which the port is opened on.
+     * * `peer` - The address of the client, the only one allowed to connect.
+     */
+    pub async fn passive(config: &FtpPassiveConfig, local: IpAddr, peer: IpAddr) -> std::io::Result<Self>{
+        let ports = usize::from(config.max_port - config.min_port) + 1;
+        let start = NEXT_PASSIVE_PORT.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..ports{
+            let port = config.min_port + ((start + offset) % ports) as u16;
+            match TcpListener::bind(SocketAddr::new(local, port)).await{
+                Ok(listener) => return Ok(DataConnection::Passive{
+                    listener,
+                    peer,
+                    accept_timeout: Duration::from_secs(config.accept_timeout)
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, "every passive port is in use"))
+    }
+
+    /**
+     * The port a passive data connection is listening on.
+     */
+    pub fn port(&self) -> Option<u16>{
+        match self{
+            DataConnection::Active(_) => None,
+            DataConnection::Passive{ listener, .. } => listener.local_addr().ok().map(|address| address.port())
+        }
+    }
+
+    /**
+     * Get the connected stream, waiting for the client to connect in passive mode.
+     *
+     * Connections from any address but the client's are closed, so that another host cannot steal
+     * the transfer by connecting to the port first.
+     */
+    pub async fn connect(self) -> std::io::Result<TcpStream>{
+        let (listener, peer, accept_timeout) = match self{
+            DataConnection::Active(stream) => return Ok(stream),
+            DataConnection::Passive{ listener, peer, accept_timeout } => (listener, peer, accept_timeout)
+        };
+        let accept = async {
+            loop{
+                let (stream, address) = listener.accept().await?;
+                if same_host(address.ip(), peer){
+                    return Ok(stream);
+                }
+                tracing::warn!(%address, %peer, "refusing data connection from another address");
+            }
+        };
+        tokio::time::timeout(accept_timeout, accept).await
+            .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the client did not connect")))
+    }
+}
+
+/**
+ * Compare addresses, treating an IPv4-mapped IPv6 address as its IPv4 address.
+ */
+fn same_host(a: IpAddr, b: IpAddr) -> bool{
+    a.to_canonical() == b.to_canonical()
+}
@@ -0,0 +1,206 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::server_utils::paths::resolve_segments_under_root;
+use crate::server_utils::FileOpenStatus;
+
+/**
+ * The files an FTP session can reach: a directory of the host, which the client sees as "/".
+ *
+ * Client paths are absolute, or relative to the current directory. "." and ".." are resolved
+ * before the host filesystem is touched, with ".." at the root staying there as it does under
+ * chroot, so no path can name anything outside the root. Symlinks leading out of the root are
+ * refused.
+ */
+pub struct VirtualFilesystem{
+    // the host directory shown to the client as "/"
+    root: PathBuf,
+    // as the client sees it, always absolute and normalized
+    current_directory: String
+}
+
+impl VirtualFilesystem{
+    /**
+     * # Arguments
+     * * `root` - The host directory shown to the client as "/", which starts as the current directory.
+     */
+    pub fn new(root: PathBuf) -> Self{
+        Self{ root, current_directory: String::from("/") }
+    }
+
+    /**
+     * The current directory, as the client sees it.
+     */
+    pub fn current_directory(&self) -> &str{
+        &self.current_directory
+    }
+
+    /**
+     * Join a client path to the current directory, unless it is absolute.
+     */
+    fn join(&self, path: &str) -> String{
+        if path.starts_with('/') {path.to_string()} else {format!("{}/{path}", self.current_directory)}
+    }
+
+    /**
+     * Map a client path onto an existing file or directory of the host.
+     *
+     * # Returns
+     * The canonical host path, or FORBIDDEN if a symlink leads out of the root, DNE if it does not
+     * exist, and ERROR if the filesystem could not be queried.
+     */
+    pub async fn resolve(&self, path: &str) -> Result<PathBuf, FileOpenStatus>{
+        let joined = self.join(path);
+        let segments = normalize(&joined);
+        resolve_segments_under_root(&self.root, &segments).await
+    }
+
+    /**
+     * Map a client path onto a file to write, which need not exist yet, though its directory must.
+     *
+     * # Returns
+     * The host path, or FORBIDDEN if a symlink leads out of the root or the path names the root
+     * itself, DNE if its directory does not exist, and ERROR if the filesystem could not be queried.
+     */
+    pub async fn resolve_new(&self, path: &str) -> Result<PathBuf, FileOpenStatus>{
+        let joined = self.join(path);
+        let segments = normalize(&joined);
+        let Some((name, directory)) = segments.split_last() else {
+            return Err(FileOpenStatus::FORBIDDEN);
+        };
+        match resolve_segments_under_root(&self.root, &segments).await{
+            Err(FileOpenStatus::DNE) => {},
+            resolved => return resolved
+        }
+        let file = resolve_segments_under_root(&self.root, directory).await?.join(name);
+        // a dangling symlink does not resolve, but writing to it would create its target
+        match tokio::fs::symlink_metadata(&file).await{
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(file),
+            Ok(_) => Err(FileOpenStatus::FORBIDDEN),
+            Err(_) => Err(FileOpenStatus::ERROR)
+        }
+    }
+
+    /**
+     * Change the current directory.
+     *
+     * # Returns
+     * FORBIDDEN if a symlink leads out of the root, DNE if it is not a directory, and ERROR if the
+     * filesystem could not be queried.
+     */
+    pub async fn change_directory(&mut self, path: &str) -> Result<(), FileOpenStatus>{
+        let joined = self.join(path);
+        let segments = normalize(&joined);
+        let resolved = resolve_segments_under_root(&self.root, &segments).await?;
+        if !tokio::fs::metadata(&resolved).await.is_ok_and(|metadata| metadata.is_dir()){
+            return Err(FileOpenStatus::DNE);
+        }
+        self.current_directory = format!("/{}", segments.join("/"));
+        Ok(())
+    }
+}
+
+/**
+ * Split an absolute client path into its segments, dropping empty and "." segments, and removing
+ * the previous segment for each "..", which does nothing at the root.
+ */
+fn normalize(path: &str) -> Vec<&str>{
+    let mut segments = Vec::new();
+    for segment in path.split('/'){
+        match segment{
+            "" | "." => {},
+            ".." => {
+                segments.pop();
+            },
+            _ => segments.push(segment)
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests{
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /**
+     * A root holding docs/a.txt, next to a directory outside of it holding secret.txt, with
+     * symlinks from the root out to both.
+     */
+    fn filesystem() -> (VirtualFilesystem, PathBuf, TempDir){
+        let directory = tempfile::tempdir().unwrap();
+        let base = directory.path().canonicalize().unwrap();
+        let root = base.join("root");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/a.txt"), "a").unwrap();
+        std::fs::create_dir(base.join("outside")).unwrap();
+        std::fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+        symlink(base.join("outside"), root.join("escape")).unwrap();
+        symlink(base.join("outside/secret.txt"), root.join("leak.txt")).unwrap();
+        symlink(base.join("outside/new.txt"), root.join("dangling")).unwrap();
+        (VirtualFilesystem::new(root.clone()), root, directory)
+    }
+
+    #[tokio::test]
+    async fn parent_of_the_root_is_the_root(){
+        let (mut filesystem, _root, _directory) = filesystem();
+        assert!(filesystem.change_directory("..").await.is_ok());
+        assert_eq!(filesystem.current_directory(), "/");
+        assert!(filesystem.change_directory("../../docs").await.is_ok());
+        assert_eq!(filesystem.current_directory(), "/docs");
+        assert!(filesystem.change_directory("../..").await.is_ok());
+        assert_eq!(filesystem.current_directory(), "/");
+    }
+
+    #[tokio::test]
+    async fn parent_paths_stay_inside_the_root(){
+        let (filesystem, root, _directory) = filesystem();
+        assert!(matches!(filesystem.resolve("../../etc/passwd").await, Err(FileOpenStatus::DNE)));
+        assert_eq!(filesystem.resolve("../docs/a.txt").await.ok(), Some(root.join("docs/a.txt")));
+        assert_eq!(filesystem.resolve("/../outside/../docs/a.txt").await.ok(), Some(root.join("docs/a.txt")));
+    }
+
+    #[tokio::test]
+    async fn symlinks_out_of_the_root_are_forbidden(){
+        let (mut filesystem, _root, _directory) = filesystem();
+        assert!(matches!(filesystem.resolve("leak.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(filesystem.resolve("escape/secret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(filesystem.resolve_new("escape/new.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(filesystem.resolve_new("leak.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(filesystem.change_directory("escape").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert_eq!(filesystem.current_directory(), "/");
+    }
+
+    #[tokio::test]
+    async fn dangling_symlinks_are_not_written_through(){
+        let (filesystem, root, _directory) = filesystem();
+        assert!(matches!(filesystem.resolve("dangling").await, Err(FileOpenStatus::DNE)));
+        assert!(matches!(filesystem.resolve_new("dangling").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(!Path::new(&root).join("../outside/new.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn relative_paths_follow_the_current_directory(){
+        let (mut filesystem, root, _directory) = filesystem();
+        assert!(filesystem.change_directory("docs").await.is_ok());
+        assert_eq!(filesystem.resolve("a.txt").await.ok(), Some(root.join("docs/a.txt")));
+        assert_eq!(filesystem.resolve("./a.txt").await.ok(), Some(root.join("docs/a.txt")));
+        assert_eq!(filesystem.resolve_new("b.txt").await.ok(), Some(root.join("docs/b.txt")));
+        assert_eq!(filesystem.resolve("/docs/a.txt").await.ok(), Some(root.join("docs/a.txt")));
+        assert!(matches!(filesystem.resolve("docs/a.txt").await, Err(FileOpenStatus::DNE)));
+        assert!(matches!(filesystem.resolve_new("missing/b.txt").await, Err(FileOpenStatus::DNE)));
+        assert!(matches!(filesystem.change_directory("a.txt").await, Err(FileOpenStatus::DNE)));
+        assert_eq!(filesystem.current_directory(), "/docs");
+    }
+
+    #[tokio::test]
+    async fn root_cannot_be_written(){
+        let (filesystem, _root, _directory) = filesystem();
+        assert!(matches!(filesystem.resolve_new("/").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(filesystem.resolve_new("..").await, Err(FileOpenStatus::FORBIDDEN)));
+    }
+}
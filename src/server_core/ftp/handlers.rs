@@ -0,0 +1,761 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use futures::future::BoxFuture;
+use futures::StreamExt;
+
+use super::command::{CommandError, FtpCommand, NetworkProtocol};
+use super::data_connection::DataConnection;
+use super::filesystem::VirtualFilesystem;
+use super::registry::{CommandHandler, CommandRegistry};
+use super::session::Session;
+use super::status::ConnectionState;
+use super::transfer::{
+    list_directory, make_active_mode_data_connection, receive_file, retrieve_file, transfer_completed
+};
+use super::utils::{hash_password, valid_username};
+use crate::server_utils::FileOpenStatus;
+
+/**
+ * The commands the server implements.
+ */
+pub fn standard_commands() -> CommandRegistry{
+    CommandRegistry::default()
+        .register("USER", User)
+        .register("PASS", Pass)
+        .register("QUIT", Quit)
+        .register("PORT", Port)
+        .register("EPRT", Eprt)
+        .register("PASV", Pasv)
+        .register("EPSV", Epsv)
+        .register("TYPE", Type)
+        .register("MODE", Mode)
+        .register("STRU", Stru)
+        .register("RETR", Retr)
+        .register("STOR", Stor)
+        .register("CWD", Cwd)
+        .register("CDUP", Cdup)
+        .register("PWD", Pwd)
+        .register("LIST", List)
+        .register("NOOP", Noop)
+        .register("FEAT", Feat)
+        .register("HELP", Help)
+}
+
+/**
+ * The reply when a handler is given a command other than its own, which is a mistake in
+ * standard_commands rather than the client's.
+ */
+fn not_registered() -> String{
+    CommandError::NotImplemented.reply()
+}
+
+/**
+ * The reply to commands which need the client to be logged in.
+ */
+pub fn not_logged_in() -> String{
+    "530 Not logged in.".to_string()
+}
+
+/**
+ * The reply to a path which could not be resolved.
+ *
+ * # Arguments
+ * * `status` - Why it could not be resolved.
+ * * `not_found` - The reply if it does not exist.
+ */
+fn path_error(status: FileOpenStatus, not_found: &str) -> String{
+    match status{
+        FileOpenStatus::DNE => not_found.to_string(),
+        FileOpenStatus::FORBIDDEN => "550 Permission denied.".to_string(),
+        FileOpenStatus::ERROR | FileOpenStatus::SUCCESS => "451 Requested action aborted: local error in processing.".to_string()
+    }
+}
+
+/**
+ * The reply to PORT, PASV and EPRT after EPSV ALL.
+ */
+fn only_epsv() -> String{
+    "503 Only EPSV is allowed after EPSV ALL.".to_string()
+}
+
+pub struct User;
+
+impl CommandHandler for User{
+    fn help(&self) -> &'static str{
+        "USER <name> - log in, asking for the password unless the name is annonymous"
+    }
+
+    fn requires_login(&self) -> bool{
+        false
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::User(username) = command else {
+                return not_registered();
+            };
+            session.auth_state = do_login_flow(username, session)
+                .await
+                .unwrap_or(ConnectionState::NotLoggedIn);
+            // anonymous users see the document root, and everyone else their own home
+            let root = match session.auth_state{
+                ConnectionState::LoggedIn => home_directory(&session.state.config.ftp_homes_directory, username).await,
+                ConnectionState::Annonymous => Some(PathBuf::from(&session.state.config.document_root)),
+                _ => None
+            };
+            if root.is_none(){
+                session.auth_state = ConnectionState::NotLoggedIn;
+            }
+            session.filesystem = root.map(VirtualFilesystem::new);
+            session.user = session.filesystem.as_ref().map(|_| username.to_string());
+            match session.auth_state{
+                ConnectionState::LoggedIn => "230 User logged in".to_string(),
+                ConnectionState::Annonymous => "230 User logged in".to_string(),
+                _ => "530 Log in unsuccessful".to_string(),
+            }
+        })
+    }
+}
+
+async fn do_login_flow(username: &str, session: &mut Session) -> Result<ConnectionState, std::io::Error>{
+    // we have an original username, now, we try to authenticate them. return Ok(()) when done.
+    if username == "annonymous"{
+        return Ok(ConnectionState::Annonymous);
+    }
+    let mut password_ok = false;
+    let mut attempts = 0;
+    while !password_ok && attempts < 5{
+        attempts += 1;
+        session.reply(&format!("331 Password required for {username}.")).await?;
+        let line = session.commands.next().await.transpose()?;
+        if let Some(FtpCommand::Pass(password)) = line.and_then(|line| line.command.ok()){
+            password_ok = check_password(&session.state.config.ftp_users_directory, username, &password).await;
+            if !password_ok{
+                session.state.metrics.record_ftp_login_failure();
+                session.reply(&format!("530 Login incorrect {} attempts remaining.", 5-attempts)).await?;
+            }
+        }else{
+            return Ok(ConnectionState::NotLoggedIn);
+        }
+    }
+
+    Ok(if password_ok {ConnectionState::LoggedIn} else {ConnectionState::NotLoggedIn})
+}
+
+async fn check_password(users_directory: &str, username: &str, password: &str) -> bool{
+    // read the hashed password from users_directory/username.passwd
+    // compare the hashed password with the password given.
+    if !valid_username(username){
+        return false;
+    }
+    let password_hash: String = hash_password(password);
+    let path = Path::new(users_directory).join(format!("{username}.passwd"));
+    match tokio::fs::read_to_string(&path).await{
+        Ok(hash) => password_hash.trim() == hash.trim(),
+        // an unknown user
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => {
+            tracing::warn!(error = %e, path = %path.display(), "could not read password file");
+            false
+        }
+    }
+}
+
+/**
+ * The root of a user's files, created the first time they log in.
+ */
+async fn home_directory(homes_directory: &str, username: &str) -> Option<PathBuf>{
+    let home = Path::new(homes_directory).join(username);
+    match tokio::fs::create_dir_all(&home).await{
+        Ok(()) => Some(home),
+        Err(e) => {
+            tracing::warn!(error = %e, home = %home.display(), "could not create home directory");
+            None
+        }
+    }
+}
+
+pub struct Pass;
+
+impl CommandHandler for Pass{
+    fn help(&self) -> &'static str{
+        "PASS <password> - the password, sent right after USER"
+    }
+
+    fn requires_login(&self) -> bool{
+        false
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, _session: &'a mut Session) -> BoxFuture<'a, String>{
+        // the password is read by USER, so one arriving here was not asked for
+        Box::pin(async { "503 Login with USER first.".to_string() })
+    }
+}
+
+pub struct Quit;
+
+impl CommandHandler for Quit{
+    fn help(&self) -> &'static str{
+        "QUIT - close the connection"
+    }
+
+    fn requires_login(&self) -> bool{
+        false
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            session.auth_state = ConnectionState::Disconnected;
+            "221 Goodbye".to_string()
+        })
+    }
+}
+
+/**
+ * Connect to the client for the next transfer, in active mode.
+ *
+ * Only the client's own address is connected to, and never a privileged port, so that the server
+ * cannot be used to reach other hosts or services (the bounce attack of RFC 2577).
+ */
+async fn open_active(session: &mut Session, verb: &str, address: SocketAddr) -> String{
+    if session.epsv_all{
+        return only_epsv();
+    }
+    if address.ip().to_canonical() != session.connection.peer_addr.ip().to_canonical() || address.port() < 1024{
+        tracing::warn!(%address, "refusing active data connection to another host or a privileged port");
+        return CommandError::UnsupportedParameter.reply();
+    }
+    session.data_connection = make_active_mode_data_connection(address).await.map(DataConnection::Active);
+    match &session.data_connection{
+        Some(_) => format!("200 {verb} command successful"),
+        None => "425 Can't open data connection.".to_string()
+    }
+}
+
+pub struct Port;
+
+impl CommandHandler for Port{
+    fn help(&self) -> &'static str{
+        "PORT <h1,h2,h3,h4,p1,p2> - connect to an IPv4 address for the next transfer"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            match command{
+                FtpCommand::Port(address) => open_active(session, "PORT", *address).await,
+                _ => not_registered()
+            }
+        })
+    }
+}
+
+pub struct Eprt;
+
+impl CommandHandler for Eprt{
+    fn help(&self) -> &'static str{
+        "EPRT |<protocol>|<address>|<port>| - connect to an IPv4 or IPv6 address for the next transfer"
+    }
+
+    fn feature(&self) -> Option<&'static str>{
+        Some("EPRT")
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            match command{
+                FtpCommand::Eprt(address) => open_active(session, "EPRT", *address).await,
+                _ => not_registered()
+            }
+        })
+    }
+}
+
+pub struct Pasv;
+
+impl CommandHandler for Pasv{
+    fn help(&self) -> &'static str{
+        "PASV - listen for the client to connect for the next transfer, over IPv4"
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            if session.epsv_all{
+                return only_epsv();
+            }
+            // an address is only advertised if the client can reach it over IPv4
+            let address = match (session.state.config.ftp_passive.external_address, session.connection.local_addr.ip().to_canonical()){
+                (Some(external), _) => external,
+                (None, IpAddr::V4(local)) => local,
+                (None, IpAddr::V6(_)) => return "425 PASV is only available over IPv4, use EPSV.".to_string()
+            };
+            match session.open_passive().await{
+                Some(port) => {
+                    let [a, b, c, d] = address.octets();
+                    format!("227 Entering Passive Mode ({a},{b},{c},{d},{},{}).", port >> 8, port & 0xff)
+                },
+                None => "425 Can't open data connection.".to_string()
+            }
+        })
+    }
+}
+
+pub struct Epsv;
+
+impl CommandHandler for Epsv{
+    fn help(&self) -> &'static str{
+        "EPSV [<protocol>|ALL] - listen for the client to connect for the next transfer"
+    }
+
+    fn feature(&self) -> Option<&'static str>{
+        Some("EPSV")
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let protocol = match command{
+                FtpCommand::EpsvAll => {
+                    session.epsv_all = true;
+                    return "200 EPSV ALL command successful.".to_string();
+                },
+                FtpCommand::Epsv(protocol) => protocol,
+                _ => return not_registered()
+            };
+            // the client may name a network protocol, which must be that of the control connection
+            let local = NetworkProtocol::of(session.connection.local_addr.ip());
+            match protocol{
+                Some(protocol) if *protocol != local => format!("522 Network protocol not supported, use ({}).", local.number()),
+                _ => match session.open_passive().await{
+                    Some(port) => format!("229 Entering Extended Passive Mode (|||{port}|)."),
+                    None => "425 Can't open data connection.".to_string()
+                }
+            }
+        })
+    }
+}
+
+pub struct Type;
+
+impl CommandHandler for Type{
+    fn help(&self) -> &'static str{
+        "TYPE <A|I|E> - set the transfer type to ASCII, binary or EBCDIC"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::Type(transfer_type) = command else {
+                return not_registered();
+            };
+            session.transfer_type = transfer_type.clone();
+            format!("200 Type set to {}", session.transfer_type)
+        })
+    }
+}
+
+pub struct Mode;
+
+impl CommandHandler for Mode{
+    fn help(&self) -> &'static str{
+        "MODE <S|B|C> - set the transfer mode, of which only stream is supported"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::Mode(transfer_mode) = command else {
+                return not_registered();
+            };
+            session.transfer_mode = transfer_mode.clone();
+            format!("200 Transfer mode set to {}", session.transfer_mode)
+        })
+    }
+}
+
+pub struct Stru;
+
+impl CommandHandler for Stru{
+    fn help(&self) -> &'static str{
+        "STRU <F|R|P> - set the file structure, of which only file is supported"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::Stru(transfer_structure) = command else {
+                return not_registered();
+            };
+            session.transfer_structure = transfer_structure.clone();
+            format!("200 Transfer structure set to {}", session.transfer_structure)
+        })
+    }
+}
+
+pub struct Retr;
+
+impl CommandHandler for Retr{
+    fn help(&self) -> &'static str{
+        "RETR <path> - download a file"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::Retr(path) = command else {
+                return not_registered();
+            };
+            let Some(filesystem) = &session.filesystem else {
+                return not_logged_in();
+            };
+            let file = match filesystem.resolve(path).await{
+                Ok(file) => file,
+                Err(status) => return path_error(status, "550 File not found.")
+            };
+            let mut ds = match session.open_transfer().await{
+                Ok(ds) => ds,
+                Err(reply) => return reply
+            };
+            let result = retrieve_file(
+                &file,
+                &mut ds,
+                session.transfer_mode.clone(),
+                session.transfer_type.clone(),
+                session.transfer_structure.clone(),
+                &session.state.metrics
+            ).await;
+            session.state.metrics.record_ftp_transfer("download", transfer_completed(&result));
+            match result{
+                Ok(m) => m,
+                Err(_) => "451 Requested action aborted.".to_string()
+            }
+        })
+    }
+}
+
+pub struct Stor;
+
+impl CommandHandler for Stor{
+    fn help(&self) -> &'static str{
+        "STOR <path> - upload a file"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::Stor(path) = command else {
+                return not_registered();
+            };
+            let Some(filesystem) = &session.filesystem else {
+                return not_logged_in();
+            };
+            // anonymous users may only download
+            if session.auth_state != ConnectionState::LoggedIn{
+                return "550 Permission denied.".to_string();
+            }
+            let file = match filesystem.resolve_new(path).await{
+                Ok(file) => file,
+                Err(status) => return path_error(status, "550 Directory not found.")
+            };
+            let mut ds = match session.open_transfer().await{
+                Ok(ds) => ds,
+                Err(reply) => return reply
+            };
+            let result = receive_file(
+                &file,
+                &mut ds,
+                session.transfer_mode.clone(),
+                session.transfer_type.clone(),
+                session.transfer_structure.clone(),
+                &session.state.metrics
+            ).await;
+            session.state.metrics.record_ftp_transfer("upload", transfer_completed(&result));
+            match result{
+                Ok(m) => m,
+                Err(_) => "451 Requested action aborted.".to_string()
+            }
+        })
+    }
+}
+
+pub struct Cwd;
+
+impl CommandHandler for Cwd{
+    fn help(&self) -> &'static str{
+        "CWD <path> - change the current directory"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::Cwd(path) = command else {
+                return not_registered();
+            };
+            change_directory(session, path).await
+        })
+    }
+}
+
+async fn change_directory(session: &mut Session, path: &str) -> String{
+    let Some(filesystem) = &mut session.filesystem else {
+        return not_logged_in();
+    };
+    match filesystem.change_directory(path).await{
+        Ok(()) => "250 Directory successfully changed.".to_string(),
+        Err(status) => path_error(status, "550 Failed to change directory.")
+    }
+}
+
+pub struct Cdup;
+
+impl CommandHandler for Cdup{
+    fn help(&self) -> &'static str{
+        "CDUP - change to the parent directory"
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(change_directory(session, ".."))
+    }
+}
+
+pub struct Pwd;
+
+impl CommandHandler for Pwd{
+    fn help(&self) -> &'static str{
+        "PWD - print the current directory"
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            match &session.filesystem{
+                Some(filesystem) => format!("257 \"{}\" is the current directory", filesystem.current_directory()),
+                None => not_logged_in()
+            }
+        })
+    }
+}
+
+pub struct List;
+
+impl CommandHandler for List{
+    fn help(&self) -> &'static str{
+        "LIST [<path>] - list a directory, or the current directory"
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            let FtpCommand::List(path) = command else {
+                return not_registered();
+            };
+            let Some(filesystem) = &session.filesystem else {
+                return not_logged_in();
+            };
+            let directory = match filesystem.resolve(path.as_deref().unwrap_or(".")).await{
+                Ok(directory) => directory,
+                Err(status) => return path_error(status, "550 Directory not found.")
+            };
+            let mut ds = match session.open_transfer().await{
+                Ok(ds) => ds,
+                Err(reply) => return reply
+            };
+            let result = list_directory(&directory, &mut ds, &session.state.metrics).await;
+            session.state.metrics.record_ftp_transfer("listing", result.is_ok());
+            match result{
+                Ok(r) => r,
+                Err(_) => "451 Requested action aborted.".to_string()
+            }
+        })
+    }
+}
+
+pub struct Noop;
+
+impl CommandHandler for Noop{
+    fn help(&self) -> &'static str{
+        "NOOP - do nothing"
+    }
+
+    fn requires_login(&self) -> bool{
+        false
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, _session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async { "200 NOOP command successful.".to_string() })
+    }
+}
+
+pub struct Feat;
+
+impl CommandHandler for Feat{
+    fn help(&self) -> &'static str{
+        "FEAT - list the extensions the server supports"
+    }
+
+    fn requires_login(&self) -> bool{
+        false
+    }
+
+    fn handle<'a>(&'a self, _command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move { session.state.commands.feat_reply() })
+    }
+}
+
+pub struct Help;
+
+impl CommandHandler for Help{
+    fn help(&self) -> &'static str{
+        "HELP [<command>] - describe a command, or list every command"
+    }
+
+    fn requires_login(&self) -> bool{
+        false
+    }
+
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>{
+        Box::pin(async move {
+            match command{
+                FtpCommand::Help(verb) => session.state.commands.help_reply(verb.as_deref()),
+                _ => not_registered()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::*;
+    use super::super::session::tests::session;
+    use super::super::status::TransferType;
+
+    async fn run(handler: &dyn CommandHandler, command: FtpCommand, session: &mut Session) -> String{
+        handler.handle(&command, session).await
+    }
+
+    async fn log_in_anonymously(session: &mut Session){
+        assert_eq!(run(&User, FtpCommand::User("annonymous".to_string()), session).await, "230 User logged in");
+    }
+
+    #[tokio::test]
+    async fn anonymous_users_see_the_document_root(){
+        let (mut session, _client, _directory) = session();
+        assert_eq!(run(&Pwd, FtpCommand::Pwd, &mut session).await, "530 Not logged in.");
+        log_in_anonymously(&mut session).await;
+        assert_eq!(session.user.as_deref(), Some("annonymous"));
+        assert!(session.auth_state == ConnectionState::Annonymous);
+        assert_eq!(run(&Pwd, FtpCommand::Pwd, &mut session).await, "257 \"/\" is the current directory");
+    }
+
+    #[tokio::test]
+    async fn users_log_in_with_their_password(){
+        let (mut session, mut client, directory) = session();
+        std::fs::write(directory.path().join("users/alice.passwd"), hash_password("s3cret")).unwrap();
+        client.write_all(b"PASS s3cret\r\n").await.unwrap();
+        assert_eq!(run(&User, FtpCommand::User("alice".to_string()), &mut session).await, "230 User logged in");
+        assert!(session.auth_state == ConnectionState::LoggedIn);
+        assert!(directory.path().join("homes/alice").is_dir());
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).await.unwrap();
+        assert_eq!(line, "331 Password required for alice.\r\n");
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_refused(){
+        let (mut session, mut client, directory) = session();
+        std::fs::write(directory.path().join("users/alice.passwd"), hash_password("s3cret")).unwrap();
+        client.write_all(b"PASS guess\r\nQUIT\r\n").await.unwrap();
+        assert_eq!(run(&User, FtpCommand::User("alice".to_string()), &mut session).await, "530 Log in unsuccessful");
+        assert!(session.auth_state == ConnectionState::NotLoggedIn);
+        assert!(session.filesystem.is_none());
+        assert!(session.user.is_none());
+    }
+
+    #[tokio::test]
+    async fn unreadable_password_file_is_a_failed_login(){
+        let (mut session, mut client, directory) = session();
+        std::fs::write(directory.path().join("users/alice.passwd"), [0xff, 0xfe]).unwrap();
+        std::fs::create_dir(directory.path().join("users/bob.passwd")).unwrap();
+        client.write_all(b"PASS s3cret\r\nQUIT\r\nPASS s3cret\r\nQUIT\r\n").await.unwrap();
+        assert_eq!(run(&User, FtpCommand::User("alice".to_string()), &mut session).await, "530 Log in unsuccessful");
+        assert_eq!(run(&User, FtpCommand::User("bob".to_string()), &mut session).await, "530 Log in unsuccessful");
+        assert!(session.filesystem.is_none());
+    }
+
+    #[tokio::test]
+    async fn type_sets_the_transfer_type(){
+        let (mut session, _client, _directory) = session();
+        assert_eq!(run(&Type, FtpCommand::Type(TransferType::Binary), &mut session).await, "200 Type set to Binary");
+        assert!(session.transfer_type == TransferType::Binary);
+        assert_eq!(run(&Type, FtpCommand::Type(TransferType::Ascii), &mut session).await, "200 Type set to ASCII");
+    }
+
+    #[tokio::test]
+    async fn cwd_and_pwd(){
+        let (mut session, _client, directory) = session();
+        std::fs::create_dir_all(directory.path().join("public/docs/old")).unwrap();
+        log_in_anonymously(&mut session).await;
+        // the parent of the root is the root, as under chroot
+        assert_eq!(run(&Cdup, FtpCommand::Cdup, &mut session).await, "250 Directory successfully changed.");
+        assert_eq!(run(&Cwd, FtpCommand::Cwd("docs".to_string()), &mut session).await, "250 Directory successfully changed.");
+        assert_eq!(run(&Cwd, FtpCommand::Cwd("old".to_string()), &mut session).await, "250 Directory successfully changed.");
+        assert_eq!(run(&Pwd, FtpCommand::Pwd, &mut session).await, "257 \"/docs/old\" is the current directory");
+        assert_eq!(run(&Cwd, FtpCommand::Cwd("missing".to_string()), &mut session).await, "550 Failed to change directory.");
+        assert_eq!(run(&Cdup, FtpCommand::Cdup, &mut session).await, "250 Directory successfully changed.");
+        assert_eq!(run(&Pwd, FtpCommand::Pwd, &mut session).await, "257 \"/docs\" is the current directory");
+        assert_eq!(run(&Cwd, FtpCommand::Cwd("/".to_string()), &mut session).await, "250 Directory successfully changed.");
+        assert_eq!(run(&Pwd, FtpCommand::Pwd, &mut session).await, "257 \"/\" is the current directory");
+    }
+
+    #[tokio::test]
+    async fn epsv_all_refuses_other_data_commands(){
+        let (mut session, _client, _directory) = session();
+        log_in_anonymously(&mut session).await;
+        assert_eq!(run(&Epsv, FtpCommand::EpsvAll, &mut session).await, "200 EPSV ALL command successful.");
+        let address = SocketAddr::from(([127, 0, 0, 1], 50001));
+        assert_eq!(run(&Port, FtpCommand::Port(address), &mut session).await, only_epsv());
+        assert_eq!(run(&Eprt, FtpCommand::Eprt(address), &mut session).await, only_epsv());
+        assert_eq!(run(&Pasv, FtpCommand::Pasv, &mut session).await, only_epsv());
+        assert!(session.data_connection.is_none());
+    }
+
+    #[tokio::test]
+    async fn active_mode_only_connects_to_the_client(){
+        let (mut session, _client, _directory) = session();
+        log_in_anonymously(&mut session).await;
+        let other_host = SocketAddr::from(([10, 0, 0, 1], 50001));
+        assert!(run(&Port, FtpCommand::Port(other_host), &mut session).await.starts_with("504 "));
+        let privileged = SocketAddr::from(([127, 0, 0, 1], 22));
+        assert!(run(&Eprt, FtpCommand::Eprt(privileged), &mut session).await.starts_with("504 "));
+    }
+
+    #[tokio::test]
+    async fn epsv_refuses_another_network_protocol(){
+        let (mut session, _client, _directory) = session();
+        log_in_anonymously(&mut session).await;
+        assert_eq!(
+            run(&Epsv, FtpCommand::Epsv(Some(NetworkProtocol::Ipv6)), &mut session).await,
+            "522 Network protocol not supported, use (1)."
+        );
+    }
+
+    #[tokio::test]
+    async fn anonymous_users_cannot_upload(){
+        let (mut session, _client, _directory) = session();
+        log_in_anonymously(&mut session).await;
+        assert_eq!(run(&Stor, FtpCommand::Stor("new.txt".to_string()), &mut session).await, "550 Permission denied.");
+    }
+
+    #[tokio::test]
+    async fn feat_and_help(){
+        let (mut session, _client, _directory) = session();
+        assert_eq!(run(&Feat, FtpCommand::Feat, &mut session).await, "211-Features:\r\n EPRT\r\n EPSV\r\n211 End");
+        assert_eq!(
+            run(&Help, FtpCommand::Help(Some("RETR".to_string())), &mut session).await,
+            "214 Syntax: RETR <path> - download a file"
+        );
+        assert_eq!(run(&Help, FtpCommand::Help(Some("SITE".to_string())), &mut session).await, "502 Unknown command SITE.");
+        let every = run(&Help, FtpCommand::Help(None), &mut session).await;
+        assert!(every.starts_with("214-") && every.ends_with("214 Help OK."));
+        assert!(every.contains(" QUIT - close the connection\r\n"));
+    }
+
+    #[tokio::test]
+    async fn wrong_command_for_handler(){
+        let (mut session, _client, _directory) = session();
+        assert_eq!(run(&Type, FtpCommand::Noop, &mut session).await, not_registered());
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+
+use futures::future::BoxFuture;
+
+use super::command::FtpCommand;
+use super::session::Session;
+
+/**
+ * Runs one FTP command.
+ *
+ * Handlers are registered by verb in a CommandRegistry, which also builds the FEAT and HELP
+ * replies from them.
+ */
+pub trait CommandHandler: Send + Sync{
+    /**
+     * The syntax of the command and what it does, listed by HELP, such as "RETR <path> - download a file".
+     */
+    fn help(&self) -> &'static str;
+
+    /**
+     * The line advertising the command in the FEAT reply, for commands which extend RFC 959.
+     */
+    fn feature(&self) -> Option<&'static str>{
+        None
+    }
+
+    /**
+     * Whether the client must be logged in to run the command, which is checked before it is run.
+     */
+    fn requires_login(&self) -> bool{
+        true
+    }
+
+    /**
+     * Run the command.
+     *
+     * # Arguments
+     * * `command` - The parsed command, one of those the handler is registered for.
+     * * `session` - The state of the connection, which the command may change.
+     *
+     * # Returns
+     * The reply to send.
+     */
+    fn handle<'a>(&'a self, command: &'a FtpCommand, session: &'a mut Session) -> BoxFuture<'a, String>;
+}
+
+/**
+ * The commands the server implements, keyed by verb.
+ */
+#[derive(Default)]
+pub struct CommandRegistry{
+    handlers: BTreeMap<&'static str, Box<dyn CommandHandler>>
+}
+
+impl CommandRegistry{
+    /**
+     * Register the handler of a verb, replacing any earlier one.
+     *
+     * # Arguments
+     * * `verb` - The command name, in upper case.
+     * * `handler` - Runs the command.
+     */
+    pub fn register(mut self, verb: &'static str, handler: impl CommandHandler + 'static) -> Self{
+        self.handlers.insert(verb, Box::new(handler));
+        self
+    }
+
+    /**
+     * Find the handler of a verb, which must be in upper case.
+     */
+    pub fn get(&self, verb: &str) -> Option<&dyn CommandHandler>{
+        self.handlers.get(verb).map(|handler| handler.as_ref())
+    }
+
+    /**
+     * The reply to FEAT, listing the extensions of the registered commands.
+     */
+    pub fn feat_reply(&self) -> String{
+        let mut reply = String::from("211-Features:\r\n");
+        for feature in self.handlers.values().filter_map(|handler| handler.feature()){
+            reply.push_str(&format!(" {feature}\r\n"));
+        }
+        reply.push_str("211 End");
+        reply
+    }
+
+    /**
+     * The reply to HELP, describing one command, or listing every command.
+     *
+     * # Arguments
+     * * `verb` - The command asked about, in upper case.
+     */
+    pub fn help_reply(&self, verb: Option<&str>) -> String{
+        match verb{
+            Some(verb) => match self.get(verb){
+                Some(handler) => format!("214 Syntax: {}", handler.help()),
+                None => format!("502 Unknown command {verb}.")
+            },
+            None => {
+                let mut reply = String::from("214-The following commands are recognized.\r\n");
+                for handler in self.handlers.values(){
+                    reply.push_str(&format!(" {}\r\n", handler.help()));
+                }
+                reply.push_str("214 Help OK.");
+                reply
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    struct Reply(&'static str, Option<&'static str>);
+
+    impl CommandHandler for Reply{
+        fn help(&self) -> &'static str{
+            self.0
+        }
+
+        fn feature(&self) -> Option<&'static str>{
+            self.1
+        }
+
+        fn handle<'a>(&'a self, _command: &'a FtpCommand, _session: &'a mut Session) -> BoxFuture<'a, String>{
+            Box::pin(async { "200 OK".to_string() })
+        }
+    }
+
+    #[test]
+    fn later_registration_replaces_earlier(){
+        let registry = CommandRegistry::default()
+            .register("SIZE", Reply("SIZE <path> - old", None))
+            .register("SIZE", Reply("SIZE <path> - new", None));
+        assert_eq!(registry.get("SIZE").unwrap().help(), "SIZE <path> - new");
+        assert!(registry.get("size").is_none());
+        assert!(registry.get("MDTM").is_none());
+    }
+
+    #[test]
+    fn commands_need_login_unless_they_say_otherwise(){
+        let registry = CommandRegistry::default().register("SIZE", Reply("SIZE <path>", None));
+        assert!(registry.get("SIZE").unwrap().requires_login());
+    }
+
+    #[test]
+    fn feat_lists_only_extensions(){
+        let registry = CommandRegistry::default()
+            .register("SIZE", Reply("SIZE <path>", Some("SIZE")))
+            .register("NOOP", Reply("NOOP", None))
+            .register("MDTM", Reply("MDTM <path>", Some("MDTM")));
+        assert_eq!(registry.feat_reply(), "211-Features:\r\n MDTM\r\n SIZE\r\n211 End");
+        assert_eq!(CommandRegistry::default().feat_reply(), "211-Features:\r\n211 End");
+    }
+
+    #[test]
+    fn help_describes_one_or_every_command(){
+        let registry = CommandRegistry::default()
+            .register("SIZE", Reply("SIZE <path> - the size of a file", None))
+            .register("NOOP", Reply("NOOP - do nothing", None));
+        assert_eq!(registry.help_reply(Some("SIZE")), "214 Syntax: SIZE <path> - the size of a file");
+        assert_eq!(registry.help_reply(Some("MDTM")), "502 Unknown command MDTM.");
+        assert_eq!(
+            registry.help_reply(None),
+            "214-The following commands are recognized.\r\n NOOP - do nothing\r\n SIZE <path> - the size of a file\r\n214 Help OK."
+        );
+    }
+
+    #[test]
+    fn standard_commands_are_registered(){
+        let registry = super::super::standard_commands();
+        for verb in ["USER", "PASS", "QUIT", "NOOP", "FEAT", "HELP"]{
+            assert!(!registry.get(verb).unwrap().requires_login(), "{verb}");
+        }
+        for verb in ["PORT", "EPRT", "PASV", "EPSV", "TYPE", "RETR", "STOR", "CWD", "CDUP", "PWD", "LIST"]{
+            assert!(registry.get(verb).unwrap().requires_login(), "{verb}");
+        }
+        assert_eq!(registry.feat_reply(), "211-Features:\r\n EPRT\r\n EPSV\r\n211 End");
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::FramedRead;
+
+use crate::server_core::connection_info::ConnectionInfo;
+use super::command::{FtpCodec, MAX_LINE_LENGTH};
+use super::data_connection::DataConnection;
+use super::filesystem::VirtualFilesystem;
+use super::status::{ConnectionState, TransferMode, TransferStructure, TransferType};
+use super::FtpState;
+
+/**
+ * The reading side of a control connection, a TCP stream except in tests.
+ */
+pub type ControlReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/**
+ * The writing side of a control connection.
+ */
+pub type ControlWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/**
+ * The state of an FTP control connection, which commands read and change.
+ */
+pub struct Session{
+    pub connection: ConnectionInfo,
+    pub state: Arc<FtpState>,
+    // the commands still to come, also read by USER to wait for the password
+    pub commands: FramedRead<ControlReader, FtpCodec>,
+    // replies are written here
+    pub control: ControlWriter,
+    pub auth_state: ConnectionState,
+    // the name the client logged in with
+    pub user: Option<String>,
+    // used by the next transfer, which closes it
+    pub data_connection: Option<DataConnection>,
+    // set by EPSV ALL, after which the client may only use EPSV
+    pub epsv_all: bool,
+    pub transfer_type: TransferType,
+    pub transfer_mode: TransferMode,
+    pub transfer_structure: TransferStructure,
+    // the files the user can reach, None until they log in
+    pub filesystem: Option<VirtualFilesystem>
+}
+
+impl Session{
+    /**
+     * Start a session on a new control connection, before the greeting is sent.
+     *
+     * # Arguments
+     * * `reader` - Where commands are read from.
+     * * `control` - Where replies are written.
+     * * `connection` - What is known about the connection.
+     * * `state` - State shared by every FTP connection.
+     */
+    pub fn new(
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        control: impl AsyncWrite + Send + Unpin + 'static,
+        connection: ConnectionInfo,
+        state: Arc<FtpState>) -> Self
+    {
+        Self{
+            connection,
+            state,
+            commands: FramedRead::new(Box::new(reader), FtpCodec::new(MAX_LINE_LENGTH)),
+            control: Box::new(control),
+            auth_state: ConnectionState::NotLoggedIn,
+            user: None,
+            data_connection: None,
+            epsv_all: false,
+            transfer_type: TransferType::Ascii,
+            transfer_mode: TransferMode::Stream,
+            transfer_structure: TransferStructure::File,
+            filesystem: None
+        }
+    }
+
+    /**
+     * Write a reply to the client, adding the line ending.
+     *
+     * Commands return their final reply, so this is for those sent before it, such as the 150
+     * which starts a transfer.
+     */
+    pub async fn reply(&mut self, reply: &str) -> Result<(), tokio::io::Error>{
+        self.control.write_all(format!("{reply}\r\n").as_bytes()).await
+    }
+
+    /**
+     * Listen for a passive data connection from the client, replacing any earlier data connection.
+     *
+     * # Returns
+     * The port it listens on, or None if no port could be opened.
+     */
+    pub async fn open_passive(&mut self) -> Option<u16>{
+        let local = self.connection.local_addr.ip();
+        match DataConnection::passive(&self.state.config.ftp_passive, local, self.connection.peer_addr.ip()).await{
+            Ok(data) => {
+                let port = data.port()?;
+                tracing::debug!(port, "listening for passive data connection");
+                self.data_connection = Some(data);
+                Some(port)
+            },
+            Err(e) => {
+                tracing::warn!(error = %e, "could not open passive data connection");
+                None
+            }
+        }
+    }
+
+    /**
+     * Open the data connection set up for the next transfer, and tell the client the transfer is
+     * starting.
+     *
+     * # Returns
+     * The data stream, or the reply to send if there is none.
+     */
+    pub async fn open_transfer(&mut self) -> Result<TcpStream, String>{
+        let Some(data) = self.data_connection.take() else {
+            return Err("425 No data connection established.".to_string());
+        };
+        let stream = match data.connect().await{
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::debug!(error = %e, "could not open data connection");
+                return Err("425 Can't open data connection.".to_string());
+            }
+        };
+        match self.reply("150 Opening data connection.").await{
+            Ok(()) => Ok(stream),
+            Err(_) => Err("426 Connection closed; transfer aborted.".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests{
+    use std::net::SocketAddr;
+    use std::time::SystemTime;
+
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::server_utils::Config;
+    use super::super::standard_commands;
+
+    /**
+     * A session on an in-memory control connection, before login.
+     *
+     * Anonymous users are served public/ of the returned directory, passwords are read from
+     * users/, and homes are made in homes/.
+     *
+     * # Returns
+     * The session, the client's end of the control connection, and the directory, which is
+     * removed when dropped.
+     */
+    pub fn session() -> (Session, DuplexStream, TempDir){
+        let directory = tempfile::tempdir().unwrap();
+        for name in ["public", "users", "homes"]{
+            std::fs::create_dir(directory.path().join(name)).unwrap();
+        }
+        let config_path = directory.path().join("config.yaml");
+        std::fs::write(&config_path, format!(
+            "document_root: {0}/public\nftp_users_directory: {0}/users\nftp_homes_directory: {0}/homes\n",
+            directory.path().display()
+        )).unwrap();
+        let state = Arc::new(FtpState{
+            config: Arc::new(Config::load(&config_path).unwrap()),
+            access_log: None,
+            metrics: Arc::new(Metrics::new()),
+            commands: standard_commands()
+        });
+        let connection = ConnectionInfo{
+            id: 0,
+            peer_addr: SocketAddr::from(([127, 0, 0, 1], 50000)),
+            local_addr: SocketAddr::from(([127, 0, 0, 1], 2121)),
+            accepted_at: SystemTime::now(),
+            tls: None
+        };
+        let (client, server) = tokio::io::duplex(4096);
+        let (reader, control) = tokio::io::split(server);
+        (Session::new(reader, control, connection, state), client, directory)
+    }
+
+    #[tokio::test]
+    async fn replies_end_with_crlf(){
+        let (mut session, mut client, _directory) = session();
+        session.reply("200 OK").await.unwrap();
+        drop(session);
+        let mut received = String::new();
+        client.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, "200 OK\r\n");
+    }
+
+    #[tokio::test]
+    async fn transfer_needs_a_data_connection(){
+        let (mut session, _client, _directory) = session();
+        assert!(matches!(session.open_transfer().await, Err(reply) if reply.starts_with("425 ")));
+    }
+}
@@ -39,6 +39,7 @@ impl std::fmt::Display for TransferMode{
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum TransferType{
     Ascii,  // 7-bit ASCII data for text files
     Binary, // 8-bit bytes for images
@@ -47,12 +48,12 @@ pub enum TransferType{
 
 impl PartialEq for TransferType{
     fn eq(&self, other: &Self) -> bool{
-        match (self, other){
-            (TransferType::Ascii, TransferType::Ascii) => true,
-            (TransferType::Binary, TransferType::Binary) => true,
-            (TransferType::EBCDIC, TransferType::EBCDIC) => true,
-            _ => false
-        }
+        matches!(
+            (self, other),
+            (TransferType::Ascii, TransferType::Ascii)
+                | (TransferType::Binary, TransferType::Binary)
+                | (TransferType::EBCDIC, TransferType::EBCDIC)
+        )
     }
 }
 
@@ -81,13 +82,13 @@ impl std::fmt::Display for TransferType{
 
 impl PartialEq for ConnectionState{
     fn eq(&self, other: &Self) -> bool{
-        match (self, other){
-            (ConnectionState::NotLoggedIn, ConnectionState::NotLoggedIn) => true,
-            (ConnectionState::Disconnected, ConnectionState::Disconnected) => true,
-            (ConnectionState::LoggedIn, ConnectionState::LoggedIn) => true,
-            (ConnectionState::Annonymous, ConnectionState::Annonymous) => true,
-            _ => false
-        }
+        matches!(
+            (self, other),
+            (ConnectionState::NotLoggedIn, ConnectionState::NotLoggedIn)
+                | (ConnectionState::Disconnected, ConnectionState::Disconnected)
+                | (ConnectionState::LoggedIn, ConnectionState::LoggedIn)
+                | (ConnectionState::Annonymous, ConnectionState::Annonymous)
+        )
     }
 }
 
@@ -99,12 +100,12 @@ pub enum TransferStructure{
 // equality
 impl PartialEq for TransferStructure{
     fn eq(&self, other: &Self) -> bool{
-        match (self, other){
-            (TransferStructure::File, TransferStructure::File) => true,
-            (TransferStructure::Record, TransferStructure::Record) => true,
-            (TransferStructure::Page, TransferStructure::Page) => true,
-            _ => false
-        }
+        matches!(
+            (self, other),
+            (TransferStructure::File, TransferStructure::File)
+                | (TransferStructure::Record, TransferStructure::Record)
+                | (TransferStructure::Page, TransferStructure::Page)
+        )
     }
 }
 
@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use async_std::fs::File;
+use async_std::io::{ReadExt, WriteExt};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::metrics::Metrics;
+use crate::server_utils::read_directory;
+use super::status::{TransferMode, TransferStructure, TransferType};
+
+/**
+ * Whether a transfer ran to completion, rather than failing or being refused.
+ */
+pub fn transfer_completed(result: &Result<String, tokio::io::Error>) -> bool{
+    matches!(result, Ok(reply) if reply.starts_with("226"))
+}
+
+pub async fn list_directory(path: &Path, data_stream:  &mut TcpStream, metrics: &Metrics) -> Result<String, tokio::io::Error>{
+
+    let mut listing = String::new();
+
+    for entry in read_directory(path)?{
+        let file_type = if entry.is_dir { "d" } else { "-" };
+
+        listing.push_str(&format!(
+            "{}rw-r--r-- 1 user group {:>8} {}\r\n",
+            file_type, entry.size, entry.name
+        ));
+    }
+    tracing::debug!(entries = listing.lines().count(), "sending directory listing");
+    data_stream.write_all(listing.as_bytes()).await?;
+    metrics.record_ftp_bytes_sent(listing.len() as u64);
+
+    Ok("226 Directory send OK.".to_string())
+}
+
+pub async fn retrieve_file(
+    path: &Path, 
+    stream: &mut TcpStream, 
+    mode: TransferMode, 
+    data_type: TransferType, 
+    structure: TransferStructure, 
+    metrics: &Metrics) -> Result<String, tokio::io::Error>
+{
+    // make sure structure is File, or send error NOT IMPLEMENTED
+    if structure != TransferStructure::File{
+        return Ok(String::from("504 Command not implemented for that parameter. (Can only handle File STRU)"));
+    }
+
+    // we need to make sure the file actually exists.
+    let file = match File::open(path).await{
+        Ok(file) => file,
+        Err(_) => return Ok(String::from("550 File not found."))
+    };
+    // based on the transfer mode, we need to send the file in the correct way.
+    match mode {
+        TransferMode::Stream => {
+            let mut reader = file;
+            let mut buffer = [0u8; 1024];
+            loop{
+                let bytes_read = reader.read(&mut buffer).await?;
+                if bytes_read == 0{
+                    break;
+                }
+                match data_type {
+                    TransferType::Ascii => {
+                        let mut ascii = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
+                        ascii = ascii.replace("\n", "\r\n");
+                        stream.write_all(ascii.as_bytes()).await?;
+                        metrics.record_ftp_bytes_sent(ascii.len() as u64);
+                    },
+                    TransferType::Binary => {
+                        stream.write_all(&buffer[..bytes_read]).await?;
+                        metrics.record_ftp_bytes_sent(bytes_read as u64);
+                    },
+                    _ => {
+                        // Error, we don't support this type.
+                        return Err(io::Error::other("Unsupported data type."));
+                    }
+                }
+            }
+        },
+        _ => {
+            // Server error
+            return Err(io::Error::other("Server error."));
+        }
+    }
+
+    Ok(String::from("226 Transfer complete."))
+}
+
+pub async fn receive_file(
+    path: &Path, 
+    stream: &mut TcpStream, 
+    mode: TransferMode, 
+    _data_type: TransferType, 
+    structure: TransferStructure, 
+    metrics: &Metrics) -> Result<String, tokio::io::Error>
+{
+    // make sure structure is File, or send error NOT IMPLEMENTED
+    if structure != TransferStructure::File{
+        return Ok(String::from("504 Command not implemented for that parameter. (Can only handle File STRU)"));
+    }
+
+    match mode {
+        TransferMode::Stream => {
+            let mut file = File::create(path).await?;
+            let mut buffer = [0u8; 8192];
+            loop {
+                let bytes_read = stream.read(&mut buffer).await?;
+                if bytes_read == 0 {
+                    break; // End of data
+                }
+                metrics.record_ftp_bytes_received(bytes_read as u64);
+                file.write_all(&buffer[..bytes_read]).await?;
+            }
+        },
+        _ => {
+            return Err(io::Error::other("Server error."));
+        }
+    }
+
+    Ok("226 Transfer complete".to_string())
+}
+
+pub async fn make_active_mode_data_connection(address: SocketAddr) -> Option<TcpStream>{
+    tracing::debug!(%address, "opening active mode data connection");
+    match tokio::time::timeout(Duration::from_secs(5), TcpStream::connect(address)).await{
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            tracing::debug!(error = %e, "could not open active mode data connection");
+            None
+        },
+        Err(_) => None
+    }
+}
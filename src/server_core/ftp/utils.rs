@@ -1,4 +1,3 @@
-use crate::server_core::ftp::status::ConnectionState;
 /**
  * Hash the password using sha256.
  */
@@ -8,15 +7,11 @@ pub fn hash_password(password: &str) -> String{
     sha256::digest(password)
 }
 
-pub fn auth_can_access_file(file: &str, auth_state: ConnectionState) -> bool{
-    // check if the file can be accessed by the user.
-    if auth_state == ConnectionState::Disconnected || auth_state == ConnectionState::NotLoggedIn{
-        return false;
-    }
-    // if the file is inside of "public" or "shared" directories, it is not privelaged.
-    if file.contains("public") || file.contains("shared"){
-        return true;
-    }
-    // if authenticated, give full access.
-    return auth_state == ConnectionState::LoggedIn;
-}
\ No newline at end of file
+/**
+ * Check that a username can name a password file, without reaching outside its directory.
+ */
+pub fn valid_username(username: &str) -> bool{
+    !username.is_empty()
+        && !username.starts_with('.')
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
@@ -0,0 +1,170 @@
+use http_body_util::combinators::BoxBody;
+use hyper::{body::Bytes, Request, Response, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::server_core::full_box_body;
+use crate::server_utils::{read_directory, DirectoryEntry};
+use crate::server_utils::paths::{percent_decode, resolve_under_root};
+
+/**
+ * Characters which are escaped when a file name is used in a link.
+ */
+const LINK_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'`').add(b'{').add(b'}').add(b'\\').add(b'^');
+
+/**
+ * The column a listing is sorted by, taken from the "sort" query parameter.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey{
+    Name,
+    Size,
+    Modified
+}
+
+impl From<&str> for SortKey{
+    fn from(s: &str) -> Self{
+        match s{
+            "size" => SortKey::Size,
+            "mtime" => SortKey::Modified,
+            _ => SortKey::Name
+        }
+    }
+}
+
+impl std::fmt::Display for SortKey{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result{
+        match self{
+            SortKey::Name => write!(f, "name"),
+            SortKey::Size => write!(f, "size"),
+            SortKey::Modified => write!(f, "mtime")
+        }
+    }
+}
+
+/**
+ * Render a listing of a directory below the document root.
+ *
+ * The listing is HTML, unless the client sends "Accept: application/json". It can be sorted with
+ * the query string, for example "?sort=size&order=desc". Directories are always listed first.
+ *
+ * # Arguments
+ * * `request` - The request for the directory.
+ * * `document_root` - The directory which requested paths are resolved under.
+ *
+ * # Returns
+ * The listing, or None if the path is not a directory which can be listed.
+ */
+pub async fn directory_listing<B>(request: &Request<B>, document_root: &str) -> Option<Response<BoxBody<Bytes, std::io::Error>>>{
+    let directory = resolve_under_root(document_root, request.uri().path()).await.ok()?;
+    let mut entries = read_directory(directory).ok()?;
+    let request_path = percent_decode(request.uri().path())?;
+
+    let (sort, descending) = sort_order(request.uri().query().unwrap_or(""));
+    entries.sort_by(|a, b| {
+        let ordering = match sort{
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified)
+        };
+        let ordering = if descending {ordering.reverse()} else {ordering};
+        b.is_dir.cmp(&a.is_dir).then(ordering).then_with(|| a.name.cmp(&b.name))
+    });
+
+    let wants_json = request.headers()
+        .get("Accept")
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/json"))
+        .unwrap_or(false);
+    let (content_type, body) = if wants_json{
+        ("application/json", render_json(&request_path, &entries))
+    }else{
+        ("text/html; charset=utf-8", render_html(&request_path, &entries, sort, descending))
+    };
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Content-Length", body.len())
+        .header("Vary", "Accept")
+        .body(full_box_body(body))
+        .unwrap();
+    Some(response)
+}
+
+/**
+ * Read the sort column and direction from a query string.
+ */
+fn sort_order(query: &str) -> (SortKey, bool){
+    let mut sort = SortKey::Name;
+    let mut descending = false;
+    for pair in query.split('&'){
+        match pair.split_once('='){
+            Some(("sort", value)) => sort = SortKey::from(value),
+            Some(("order", value)) => descending = value == "desc",
+            _ => {}
+        }
+    }
+    (sort, descending)
+}
+
+fn render_json(request_path: &str, entries: &[DirectoryEntry]) -> String{
+    let entries: Vec<serde_json::Value> = entries.iter()
+        .map(|entry| serde_json::json!({
+            "name": entry.name,
+            "type": if entry.is_dir {"directory"} else {"file"},
+            "size": entry.size,
+            "modified": entry.modified.map(httpdate::fmt_http_date)
+        }))
+        .collect();
+    serde_json::json!({
+        "path": request_path,
+        "entries": entries
+    }).to_string()
+}
+
+fn render_html(request_path: &str, entries: &[DirectoryEntry], sort: SortKey, descending: bool) -> String{
+    let title = format!("Index of {}", escape_html(request_path));
+    // clicking the current sort column flips its order
+    let header = |key: SortKey, label: &str| {
+        let order = if key == sort && !descending {"desc"} else {"asc"};
+        format!("<th><a href=\"?sort={key}&amp;order={order}\">{label}</a></th>")
+    };
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<table>\n<tr>{}{}{}</tr>\n",
+        header(SortKey::Name, "Name"),
+        header(SortKey::Size, "Size"),
+        header(SortKey::Modified, "Last modified")
+    );
+    if request_path != "/"{
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries{
+        let suffix = if entry.is_dir {"/"} else {""};
+        let size = if entry.is_dir {"-".to_string()} else {entry.size.to_string()};
+        let modified = entry.modified.map(httpdate::fmt_http_date).unwrap_or_default();
+        html.push_str(&format!(
+            "<tr><td><a href=\"{link}{suffix}\">{name}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            link = utf8_percent_encode(&entry.name, LINK_ESCAPES),
+            name = escape_html(&entry.name)
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String{
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars(){
+        match c{
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
@@ -0,0 +1,297 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http_body_util::combinators::BoxBody;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{body::Bytes, Method, Request, Response, StatusCode};
+
+use crate::server_core::full_box_body;
+
+/**
+ * The validators of a file, which let clients check whether their cached copy is still current.
+ */
+pub struct Validators{
+    pub etag: String,
+    pub last_modified: Option<SystemTime>
+}
+
+impl Validators{
+    /**
+     * Derive the validators of a file from its metadata.
+     *
+     * The entity tag is made from the size and the modification time of the file, so it changes
+     * whenever the file is rewritten, without having to read the file to hash it.
+     *
+     * # Arguments
+     * * `metadata` - The metadata of the file.
+     */
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self{
+        let last_modified = metadata.modified().ok();
+        let modified_nanos = last_modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        Self{
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), modified_nanos),
+            last_modified
+        }
+    }
+
+    /**
+     * Add the ETag and Last-Modified headers to a response.
+     *
+     * # Arguments
+     * * `headers` - The headers of the response.
+     */
+    pub fn insert_headers(&self, headers: &mut HeaderMap){
+        if let Ok(etag) = HeaderValue::from_str(&self.etag){
+            headers.insert("ETag", etag);
+        }
+        if let Some(last_modified) = self.last_modified{
+            let last_modified = httpdate::fmt_http_date(last_modified);
+            headers.insert("Last-Modified", HeaderValue::from_str(&last_modified).unwrap());
+        }
+    }
+
+    /**
+     * Check whether an If-Range header still describes the file.
+     *
+     * An entity tag must match strongly, and a date must be exactly the modification time.
+     *
+     * # Arguments
+     * * `if_range` - The value of the If-Range header.
+     */
+    pub fn matches_if_range(&self, if_range: &str) -> bool{
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') || if_range.starts_with("W/"){
+            return strong_match(if_range, &self.etag);
+        }
+        match (httpdate::parse_http_date(if_range), self.last_modified){
+            (Ok(date), Some(modified)) => whole_seconds(date) == whole_seconds(modified),
+            _ => false
+        }
+    }
+}
+
+/**
+ * The outcome of evaluating the conditional headers of a request.
+ */
+pub enum Precondition{
+    Proceed,
+    NotModified,
+    Failed
+}
+
+/**
+ * Evaluate If-Match, If-Unmodified-Since, If-None-Match and If-Modified-Since, in the order
+ * given by RFC 9110 section 13.2.2.
+ *
+ * # Arguments
+ * * `request` - The request whose conditional headers are evaluated.
+ * * `validators` - The validators of the file being requested.
+ */
+pub fn evaluate_preconditions<B>(request: &Request<B>, validators: &Validators) -> Precondition{
+    let headers = request.headers();
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(if_match) = header("If-Match"){
+        if !list_matches(if_match, &validators.etag, strong_match){
+            return Precondition::Failed;
+        }
+    }else if let Some(if_unmodified_since) = header("If-Unmodified-Since"){
+        if let (Ok(date), Some(modified)) = (httpdate::parse_http_date(if_unmodified_since), validators.last_modified){
+            if whole_seconds(modified) > whole_seconds(date){
+                return Precondition::Failed;
+            }
+        }
+    }
+
+    let is_read = request.method() == Method::GET || request.method() == Method::HEAD;
+    if let Some(if_none_match) = header("If-None-Match"){
+        if list_matches(if_none_match, &validators.etag, weak_match){
+            return if is_read {Precondition::NotModified} else {Precondition::Failed};
+        }
+    }else if let Some(if_modified_since) = header("If-Modified-Since"){
+        if let (true, Ok(date), Some(modified)) = (is_read, httpdate::parse_http_date(if_modified_since), validators.last_modified){
+            if whole_seconds(modified) <= whole_seconds(date){
+                return Precondition::NotModified;
+            }
+        }
+    }
+    Precondition::Proceed
+}
+
+/**
+ * Build the 304 response sent when the client's cached copy is still current.
+ *
+ * # Arguments
+ * * `validators` - The validators of the file, which are repeated in the response.
+ */
+pub fn not_modified_response(validators: &Validators) -> Response<BoxBody<Bytes, std::io::Error>>{
+    let mut response = Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .body(full_box_body(Bytes::new()))
+        .unwrap();
+    validators.insert_headers(response.headers_mut());
+    response
+}
+
+/**
+ * Build the 412 response sent when If-Match or If-Unmodified-Since do not hold.
+ */
+pub fn precondition_failed_response() -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(StatusCode::PRECONDITION_FAILED)
+        .body(full_box_body("Precondition failed"))
+        .unwrap()
+}
+
+/**
+ * Check whether a list of entity tags, or "*", matches an entity tag.
+ */
+fn list_matches(list: &str, etag: &str, compare: fn(&str, &str) -> bool) -> bool{
+    if list.trim() == "*"{
+        // the file exists, so any current representation matches
+        return true;
+    }
+    list.split(',').any(|candidate| compare(candidate.trim(), etag))
+}
+
+fn strong_match(candidate: &str, etag: &str) -> bool{
+    !candidate.starts_with("W/") && !etag.starts_with("W/") && candidate == etag
+}
+
+fn weak_match(candidate: &str, etag: &str) -> bool{
+    candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+}
+
+fn whole_seconds(time: SystemTime) -> u64{
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::time::Duration;
+
+    use super::*;
+
+    fn validators() -> Validators{
+        Validators{
+            etag: "\"abc\"".to_string(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        }
+    }
+
+    /**
+     * An HTTP date some seconds after the modification time of the validators, which may be negative.
+     */
+    fn date(offset: i64) -> String{
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let date = if offset < 0 {modified - Duration::from_secs(offset.unsigned_abs())} else {modified + Duration::from_secs(offset as u64)};
+        httpdate::fmt_http_date(date)
+    }
+
+    /**
+     * Evaluate the preconditions of a request, naming the outcome.
+     */
+    fn evaluate(method: Method, headers: &[(&str, &str)]) -> &'static str{
+        let mut request = Request::builder().method(method);
+        for (name, value) in headers{
+            request = request.header(*name, *value);
+        }
+        match evaluate_preconditions(&request.body(()).unwrap(), &validators()){
+            Precondition::Proceed => "proceed",
+            Precondition::NotModified => "304",
+            Precondition::Failed => "412"
+        }
+    }
+
+    fn get(headers: &[(&str, &str)]) -> &'static str{
+        evaluate(Method::GET, headers)
+    }
+
+    #[test]
+    fn no_conditions(){
+        assert_eq!(get(&[]), "proceed");
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison(){
+        assert_eq!(get(&[("If-None-Match", "\"abc\"")]), "304");
+        assert_eq!(get(&[("If-None-Match", "W/\"abc\"")]), "304");
+        assert_eq!(get(&[("If-None-Match", "\"x\", W/\"abc\"")]), "304");
+        assert_eq!(get(&[("If-None-Match", "*")]), "304");
+        assert_eq!(get(&[("If-None-Match", "\"other\"")]), "proceed");
+        assert_eq!(evaluate(Method::HEAD, &[("If-None-Match", "\"abc\"")]), "304");
+        // other methods fail instead of being told nothing changed
+        assert_eq!(evaluate(Method::PUT, &[("If-None-Match", "*")]), "412");
+    }
+
+    #[test]
+    fn if_match_uses_strong_comparison(){
+        assert_eq!(get(&[("If-Match", "\"abc\"")]), "proceed");
+        assert_eq!(get(&[("If-Match", "\"x\", \"abc\"")]), "proceed");
+        assert_eq!(get(&[("If-Match", "*")]), "proceed");
+        assert_eq!(get(&[("If-Match", "W/\"abc\"")]), "412");
+        assert_eq!(get(&[("If-Match", "\"other\"")]), "412");
+    }
+
+    #[test]
+    fn if_modified_since(){
+        assert_eq!(get(&[("If-Modified-Since", &date(0))]), "304");
+        assert_eq!(get(&[("If-Modified-Since", &date(60))]), "304");
+        assert_eq!(get(&[("If-Modified-Since", &date(-1))]), "proceed");
+        assert_eq!(get(&[("If-Modified-Since", "yesterday")]), "proceed");
+        // only reads can be answered with 304
+        assert_eq!(evaluate(Method::POST, &[("If-Modified-Since", &date(0))]), "proceed");
+    }
+
+    #[test]
+    fn if_unmodified_since(){
+        assert_eq!(get(&[("If-Unmodified-Since", &date(0))]), "proceed");
+        assert_eq!(get(&[("If-Unmodified-Since", &date(-1))]), "412");
+        assert_eq!(get(&[("If-Unmodified-Since", "yesterday")]), "proceed");
+    }
+
+    #[test]
+    fn entity_tags_take_precedence_over_dates(){
+        // If-Match replaces If-Unmodified-Since, which would fail
+        assert_eq!(get(&[("If-Match", "\"abc\""), ("If-Unmodified-Since", &date(-1))]), "proceed");
+        // If-None-Match replaces If-Modified-Since, which would give 304
+        assert_eq!(get(&[("If-None-Match", "\"other\""), ("If-Modified-Since", &date(0))]), "proceed");
+        // a failed If-Match is answered before If-None-Match is looked at
+        assert_eq!(get(&[("If-Match", "\"other\""), ("If-None-Match", "\"abc\"")]), "412");
+        assert_eq!(get(&[("If-Unmodified-Since", &date(-1)), ("If-Modified-Since", &date(0))]), "412");
+    }
+
+    #[test]
+    fn if_range_with_an_entity_tag(){
+        let validators = validators();
+        assert!(validators.matches_if_range("\"abc\""));
+        assert!(validators.matches_if_range(" \"abc\" "));
+        assert!(!validators.matches_if_range("W/\"abc\""));
+        assert!(!validators.matches_if_range("\"other\""));
+        let weak = Validators{ etag: "W/\"abc\"".to_string(), last_modified: None };
+        assert!(!weak.matches_if_range("W/\"abc\""));
+    }
+
+    #[test]
+    fn if_range_with_a_date(){
+        let validators = validators();
+        assert!(validators.matches_if_range(&date(0)));
+        assert!(!validators.matches_if_range(&date(1)));
+        assert!(!validators.matches_if_range(&date(-1)));
+        assert!(!validators.matches_if_range("not a date"));
+        let undated = Validators{ etag: "\"abc\"".to_string(), last_modified: None };
+        assert!(!undated.matches_if_range(&date(0)));
+    }
+
+    #[test]
+    fn not_modified_repeats_the_validators(){
+        let response = not_modified_response(&validators());
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["ETag"], "\"abc\"");
+        assert_eq!(response.headers()["Last-Modified"], date(0).as_str());
+    }
+}
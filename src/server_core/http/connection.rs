@@ -0,0 +1,426 @@
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::body::{Body, Bytes, Frame, Incoming, SizeHint};
+use hyper::header::HeaderValue;
+use hyper::{Request, Response, StatusCode, Version};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+
+use crate::metrics::Metrics;
+use crate::router::{HandlerFuture, RequestBody};
+use crate::server_core::full_box_body;
+use crate::server_core::connection_info::ConnectionInfo;
+use crate::server_utils::ConnectionConfig;
+
+/**
+ * Tracks the requests on one connection, to decide when the connection should be closed.
+ */
+pub struct ConnectionActivity{
+    in_flight: AtomicUsize,
+    requests: AtomicU64,
+    last_active: Mutex<Instant>,
+    // when the headers of the request being received must have arrived, None between requests
+    header_deadline: Mutex<Option<Instant>>,
+    // HTTP/2 reads frames between requests, so only HTTP/1 connections have a header deadline
+    http2: AtomicBool,
+    changed: Notify,
+    header_read_timeout: Duration,
+    idle_timeout: Duration,
+    max_requests: Option<u64>
+}
+
+impl ConnectionActivity{
+    /**
+     * # Arguments
+     * * `config` - The limits applied to each connection.
+     */
+    pub fn new(config: &ConnectionConfig) -> Self{
+        let header_read_timeout = Duration::from_secs(config.header_read_timeout);
+        Self{
+            in_flight: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            last_active: Mutex::new(Instant::now()),
+            // the first request is expected as soon as the connection opens
+            header_deadline: Mutex::new(Some(Instant::now() + header_read_timeout)),
+            http2: AtomicBool::new(false),
+            changed: Notify::new(),
+            header_read_timeout,
+            idle_timeout: Duration::from_secs(config.idle_timeout),
+            max_requests: config.max_requests
+        }
+    }
+
+    /**
+     * Record the start of a request, which lasts until the returned guard is dropped.
+     */
+    fn start_request(self: &Arc<Self>, version: Version) -> RequestGuard{
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        if version == Version::HTTP_2{
+            self.http2.store(true, Ordering::SeqCst);
+        }
+        *self.header_deadline.lock().unwrap() = None;
+        *self.last_active.lock().unwrap() = Instant::now();
+        self.changed.notify_waiters();
+        RequestGuard{ activity: Arc::clone(self) }
+    }
+
+    /**
+     * Record that bytes were read from the client. Between HTTP/1 requests, they start the next
+     * request, whose headers must then arrive within the header read timeout.
+     */
+    fn received(&self){
+        if self.http2.load(Ordering::SeqCst) || self.in_flight.load(Ordering::SeqCst) > 0{
+            return;
+        }
+        let mut header_deadline = self.header_deadline.lock().unwrap();
+        if header_deadline.is_none(){
+            *header_deadline = Some(Instant::now() + self.header_read_timeout);
+            self.changed.notify_waiters();
+        }
+    }
+
+    /**
+     * Whether the connection has served as many requests as it is allowed to.
+     */
+    fn exhausted(&self) -> bool{
+        self.max_requests.is_some_and(|max_requests| self.requests.load(Ordering::SeqCst) >= max_requests)
+    }
+
+    /**
+     * Wait until the connection should be shut down, either because it has been idle for too
+     * long or because it has reached its request limit.
+     *
+     * Requests in progress are unaffected, the caller is expected to shut down gracefully.
+     */
+    pub async fn wait_until_closing(&self){
+        self.wait(true).await
+    }
+
+    /**
+     * Wait until the connection has had no request in progress for the idle timeout.
+     */
+    pub async fn wait_until_idle(&self){
+        self.wait(false).await
+    }
+
+    /**
+     * Wait until a request has been started but its headers have not arrived in time.
+     *
+     * The connection should then be dropped, as its client is too slow or is holding it open.
+     * Waiting between requests is not limited by this, only by the idle timeout.
+     */
+    pub async fn wait_until_headers_overdue(&self){
+        loop{
+            let changed = self.changed.notified();
+            let header_deadline = *self.header_deadline.lock().unwrap();
+            match header_deadline{
+                Some(deadline) if Instant::now() >= deadline => return,
+                Some(deadline) => tokio::select!{
+                    _ = tokio::time::sleep_until(deadline) => {},
+                    _ = changed => {}
+                },
+                None => changed.await
+            }
+        }
+    }
+
+    async fn wait(&self, include_exhausted: bool){
+        loop{
+            // created before checking, so a change made while checking is not missed
+            let changed = self.changed.notified();
+            if include_exhausted && self.exhausted(){
+                return;
+            }
+            if self.in_flight.load(Ordering::SeqCst) > 0{
+                changed.await;
+                continue;
+            }
+            let deadline = *self.last_active.lock().unwrap() + self.idle_timeout;
+            if Instant::now() >= deadline{
+                return;
+            }
+            tokio::select!{
+                _ = tokio::time::sleep_until(deadline) => {},
+                _ = changed => {}
+            }
+        }
+    }
+}
+
+/**
+ * Marks a request as in progress until it is dropped, along with the body of its response.
+ */
+struct RequestGuard{
+    activity: Arc<ConnectionActivity>
+}
+
+impl Drop for RequestGuard{
+    fn drop(&mut self){
+        self.activity.in_flight.fetch_sub(1, Ordering::SeqCst);
+        *self.activity.last_active.lock().unwrap() = Instant::now();
+        self.activity.changed.notify_waiters();
+    }
+}
+
+/**
+ * A request body which records when it has been received in full, and counts the bytes received.
+ */
+struct TrackedBody{
+    inner: Incoming,
+    finished: Arc<AtomicBool>,
+    metrics: Arc<Metrics>
+}
+
+impl Body for TrackedBody{
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>>{
+        let this = self.get_mut();
+        let frame = match Pin::new(&mut this.inner).poll_frame(cx){
+            Poll::Ready(frame) => frame,
+            Poll::Pending => return Poll::Pending
+        };
+        if let Some(data) = frame.as_ref().and_then(|frame| frame.as_ref().ok()).and_then(Frame::data_ref){
+            this.metrics.record_http_bytes_received(data.len() as u64);
+        }
+        if frame.is_none() || this.inner.is_end_stream(){
+            this.finished.store(true, Ordering::SeqCst);
+        }
+        Poll::Ready(frame.map(|frame| frame.map_err(std::io::Error::other)))
+    }
+
+    fn is_end_stream(&self) -> bool{
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint{
+        self.inner.size_hint()
+    }
+}
+
+/**
+ * A response body which keeps its request in progress until it has been sent, or dropped.
+ */
+struct GuardedBody{
+    inner: BoxBody<Bytes, std::io::Error>,
+    _guard: RequestGuard
+}
+
+impl Body for GuardedBody{
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>>{
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool{
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint{
+        self.inner.size_hint()
+    }
+}
+
+/**
+ * A tower Layer which applies the per request limits of a connection, and adds its
+ * ConnectionInfo to the extensions of each request.
+ *
+ * A request whose body is not received within the request body timeout is answered with 408,
+ * and the response which reaches the request limit asks HTTP/1 clients to close the connection.
+ */
+pub struct ConnectionLayer{
+    activity: Arc<ConnectionActivity>,
+    info: ConnectionInfo,
+    metrics: Arc<Metrics>,
+    request_body_timeout: Duration
+}
+
+impl ConnectionLayer{
+    /**
+     * # Arguments
+     * * `activity` - The activity of the connection, shared with the task serving it.
+     * * `info` - What is known about the connection.
+     * * `metrics` - The metrics to count received bytes in.
+     * * `config` - The limits applied to each connection.
+     */
+    pub fn new(activity: Arc<ConnectionActivity>, info: ConnectionInfo, metrics: Arc<Metrics>, config: &ConnectionConfig) -> Self{
+        Self{
+            activity,
+            info,
+            metrics,
+            request_body_timeout: Duration::from_secs(config.request_body_timeout)
+        }
+    }
+}
+
+impl<S> Layer<S> for ConnectionLayer{
+    type Service = ConnectionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service{
+        ConnectionService{
+            inner,
+            activity: Arc::clone(&self.activity),
+            info: self.info.clone(),
+            metrics: Arc::clone(&self.metrics),
+            request_body_timeout: self.request_body_timeout
+        }
+    }
+}
+
+/**
+ * The tower Service produced by ConnectionLayer.
+ */
+#[derive(Clone)]
+pub struct ConnectionService<S>{
+    inner: S,
+    activity: Arc<ConnectionActivity>,
+    info: ConnectionInfo,
+    metrics: Arc<Metrics>,
+    request_body_timeout: Duration
+}
+
+impl<S> Service<Request<Incoming>> for ConnectionService<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<BoxBody<Bytes, std::io::Error>>, Error = hyper::Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody<Bytes, std::io::Error>>;
+    type Error = hyper::Error;
+    type Future = HandlerFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>{
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Incoming>) -> Self::Future{
+        let version = request.version();
+        let guard = self.activity.start_request(version);
+        let last_request = self.activity.exhausted();
+
+        let (mut parts, body) = request.into_parts();
+        parts.extensions.insert(self.info.clone());
+        let finished = Arc::new(AtomicBool::new(body.is_end_stream()));
+        let body = TrackedBody{ inner: body, finished: Arc::clone(&finished), metrics: Arc::clone(&self.metrics) };
+        let response = self.inner.call(Request::from_parts(parts, BodyExt::boxed(body)));
+        let request_body_timeout = self.request_body_timeout;
+
+        Box::pin(async move {
+            let mut response = pin!(response);
+            let (response, close) = tokio::select!{
+                response = &mut response => (response?, last_request),
+                _ = tokio::time::sleep(request_body_timeout), if !finished.load(Ordering::SeqCst) => {
+                    if finished.load(Ordering::SeqCst){
+                        (response.await?, last_request)
+                    }else{
+                        (request_timeout(), true)
+                    }
+                }
+            };
+            let mut response = response.map(|body| BodyExt::boxed(GuardedBody{ inner: body, _guard: guard }));
+            // HTTP/2 has no Connection header, its connection is closed by the caller instead
+            if close && version <= Version::HTTP_11{
+                response.headers_mut().insert("Connection", HeaderValue::from_static("close"));
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn request_timeout() -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(StatusCode::REQUEST_TIMEOUT)
+        .body(full_box_body("Request timeout"))
+        .unwrap()
+}
+
+/**
+ * A connection whose writes fail once they have waited too long for the client to read, and
+ * whose reads are reported to the activity of the connection.
+ */
+pub struct TimedIo<I>{
+    inner: I,
+    activity: Arc<ConnectionActivity>,
+    write_timeout: Duration,
+    write_deadline: Option<Pin<Box<Sleep>>>
+}
+
+impl<I> TimedIo<I>{
+    /**
+     * # Arguments
+     * * `inner` - The connection.
+     * * `activity` - The activity of the connection, which starts a request when bytes arrive.
+     * * `config` - The limits applied to each connection.
+     */
+    pub fn new(inner: I, activity: Arc<ConnectionActivity>, config: &ConnectionConfig) -> Self{
+        Self{
+            inner,
+            activity,
+            write_timeout: Duration::from_secs(config.write_timeout),
+            write_deadline: None
+        }
+    }
+}
+
+impl<I: AsyncWrite + Unpin> TimedIo<I>{
+    /**
+     * Run a write operation, starting the deadline when it first has to wait.
+     */
+    fn poll_timed<T>(&mut self, cx: &mut Context<'_>, operation: impl FnOnce(Pin<&mut I>, &mut Context<'_>) -> Poll<std::io::Result<T>>) -> Poll<std::io::Result<T>>{
+        if let Poll::Ready(result) = operation(Pin::new(&mut self.inner), cx){
+            self.write_deadline = None;
+            return Poll::Ready(result);
+        }
+        let write_timeout = self.write_timeout;
+        let deadline = self.write_deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(write_timeout)));
+        match deadline.as_mut().poll(cx){
+            Poll::Ready(()) => Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "write timed out"))),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for TimedIo<I>{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>>{
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled{
+            this.activity.received();
+        }
+        result
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for TimedIo<I>{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>>{
+        self.get_mut().poll_timed(cx, |inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[std::io::IoSlice<'_>]) -> Poll<std::io::Result<usize>>{
+        self.get_mut().poll_timed(cx, |inner, cx| inner.poll_write_vectored(cx, bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool{
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>{
+        self.get_mut().poll_timed(cx, |inner, cx| inner.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>{
+        self.get_mut().poll_timed(cx, |inner, cx| inner.poll_shutdown(cx))
+    }
+}
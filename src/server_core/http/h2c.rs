@@ -0,0 +1,409 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderMap, HeaderName, CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING, UPGRADE};
+use hyper::server::conn::http2;
+use hyper::upgrade::OnUpgrade;
+use hyper::{Request, Response, StatusCode, Version};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::sync::oneshot;
+use tower::{Layer, Service};
+
+use crate::router::HandlerFuture;
+use crate::server_utils::Http2Config;
+
+/**
+ * The bytes every HTTP/2 client sends first, RFC 9113 section 3.4.
+ */
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// the largest frame every HTTP/2 peer accepts
+const MAX_FRAME_SIZE: usize = 16384;
+const FRAME_HEADER_LENGTH: usize = 9;
+const HEADERS_FRAME: u8 = 0x1;
+const SETTINGS_FRAME: u8 = 0x4;
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+// each setting is a 16 bit identifier and a 32 bit value
+const SETTING_LENGTH: usize = 6;
+
+/**
+ * Headers which only apply to the HTTP/1.1 connection, and are not sent over HTTP/2.
+ */
+const CONNECTION_HEADERS: [&str; 7] = ["connection", "upgrade", "http2-settings", "keep-alive", "proxy-connection", "transfer-encoding", "host"];
+
+/**
+ * An upgrade to HTTP/2 accepted on an HTTP/1.1 connection, which is served once the 101 has been
+ * sent.
+ */
+pub struct PendingUpgrade{
+    on_upgrade: OnUpgrade,
+    // the client's settings, from its HTTP2-Settings header
+    settings: Vec<u8>,
+    // the request which asked for the upgrade, encoded as the headers of stream 1
+    header_block: Vec<u8>
+}
+
+/**
+ * A tower Layer which accepts "Upgrade: h2c" on cleartext HTTP/1.1 connections, RFC 7540
+ * section 3.2.
+ *
+ * The request asking for the upgrade is answered with 101, and handed back through the receiver
+ * returned with the layer, so the caller can serve the rest of the connection as HTTP/2.
+ * Requests with a body are answered over HTTP/1.1 instead, as the body would have to be received
+ * before switching protocols.
+ */
+pub struct H2cLayer{
+    upgrade: Arc<Mutex<Option<oneshot::Sender<PendingUpgrade>>>>
+}
+
+impl H2cLayer{
+    /**
+     * # Returns
+     * The layer, and the receiver of the upgrade once a request has asked for it.
+     */
+    pub fn new() -> (Self, oneshot::Receiver<PendingUpgrade>){
+        let (sender, receiver) = oneshot::channel();
+        (Self{ upgrade: Arc::new(Mutex::new(Some(sender))) }, receiver)
+    }
+}
+
+impl<S> Layer<S> for H2cLayer{
+    type Service = H2cService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service{
+        H2cService{ inner, upgrade: Arc::clone(&self.upgrade) }
+    }
+}
+
+/**
+ * The tower Service produced by H2cLayer.
+ */
+#[derive(Clone)]
+pub struct H2cService<S>{
+    inner: S,
+    upgrade: Arc<Mutex<Option<oneshot::Sender<PendingUpgrade>>>>
+}
+
+impl<S> Service<Request<Incoming>> for H2cService<S>
+where
+    S: Service<Request<Incoming>, Response = Response<BoxBody<Bytes, std::io::Error>>, Error = hyper::Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody<Bytes, std::io::Error>>;
+    type Error = hyper::Error;
+    type Future = HandlerFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>{
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Incoming>) -> Self::Future{
+        if let Some((settings, header_block)) = upgrade_request(&request){
+            if let Some(sender) = self.upgrade.lock().unwrap().take(){
+                let on_upgrade = hyper::upgrade::on(&mut request);
+                // the receiver is only gone once the connection is, and then so is the client
+                let _ = sender.send(PendingUpgrade{ on_upgrade, settings, header_block });
+                return Box::pin(async { Ok(switching_protocols()) });
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+fn switching_protocols() -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "Upgrade")
+        .header(UPGRADE, "h2c")
+        .body(Empty::new().map_err(|n| match n {}).boxed())
+        .unwrap()
+}
+
+/**
+ * Check whether a request asks for, and can be given, an upgrade to HTTP/2.
+ *
+ * # Returns
+ * The client's settings and the request encoded as the headers of stream 1, or None to answer the
+ * request over HTTP/1.1.
+ */
+fn upgrade_request<B>(request: &Request<B>) -> Option<(Vec<u8>, Vec<u8>)>{
+    let headers = request.headers();
+    if request.version() != Version::HTTP_11
+        || !has_token(headers, &UPGRADE, "h2c")
+        || !has_token(headers, &CONNECTION, "upgrade")
+        || !has_token(headers, &CONNECTION, "http2-settings"){
+        return None;
+    }
+    if headers.contains_key(TRANSFER_ENCODING) || headers.get(CONTENT_LENGTH).is_some_and(|length| length != "0"){
+        return None;
+    }
+    let mut values = headers.get_all("http2-settings").iter();
+    let (Some(value), None) = (values.next(), values.next()) else {
+        return None;
+    };
+    let settings = URL_SAFE_NO_PAD.decode(value.to_str().ok()?.trim_end_matches('=')).ok()?;
+    if !settings.len().is_multiple_of(SETTING_LENGTH){
+        return None;
+    }
+    let header_block = encode_request_headers(request)?;
+    if header_block.len() > MAX_FRAME_SIZE{
+        return None;
+    }
+    Some((settings, header_block))
+}
+
+/**
+ * Whether a comma separated header lists a token, ignoring case.
+ */
+fn has_token(headers: &HeaderMap, name: &HeaderName, token: &str) -> bool{
+    headers.get_all(name).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/**
+ * Encode the method, target and headers of a request as an HPACK header block, RFC 7541.
+ *
+ * Every field is a literal which is not indexed, so the block leaves the compression state of the
+ * connection as it was.
+ *
+ * # Returns
+ * The header block, or None if the request has no Host.
+ */
+fn encode_request_headers<B>(request: &Request<B>) -> Option<Vec<u8>>{
+    let authority = request.headers().get(HOST)?;
+    let path = request.uri().path_and_query().map_or("/", |path| path.as_str());
+    let mut block = Vec::new();
+    encode_literal(&mut block, b":method", request.method().as_str().as_bytes());
+    encode_literal(&mut block, b":scheme", b"http");
+    encode_literal(&mut block, b":authority", authority.as_bytes());
+    encode_literal(&mut block, b":path", path.as_bytes());
+    for (name, value) in request.headers(){
+        if CONNECTION_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers"){
+            continue;
+        }
+        encode_literal(&mut block, name.as_str().as_bytes(), value.as_bytes());
+    }
+    Some(block)
+}
+
+/**
+ * Append a "literal header field without indexing" with a new name.
+ */
+fn encode_literal(block: &mut Vec<u8>, name: &[u8], value: &[u8]){
+    block.push(0);
+    for string in [name, value]{
+        encode_integer(block, string.len());
+        block.extend_from_slice(string);
+    }
+}
+
+/**
+ * Append the length of a string which is not Huffman coded, as an integer with a 7 bit prefix.
+ */
+fn encode_integer(block: &mut Vec<u8>, value: usize){
+    const PREFIX_MAX: usize = 127;
+    if value < PREFIX_MAX{
+        block.push(value as u8);
+        return;
+    }
+    block.push(PREFIX_MAX as u8);
+    let mut rest = value - PREFIX_MAX;
+    while rest >= 128{
+        block.push((rest % 128) as u8 | 0x80);
+        rest /= 128;
+    }
+    block.push(rest as u8);
+}
+
+fn encode_frame(buffer: &mut Vec<u8>, frame_type: u8, flags: u8, stream: u32, payload: &[u8]){
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    buffer.push(frame_type);
+    buffer.push(flags);
+    buffer.extend_from_slice(&stream.to_be_bytes());
+    buffer.extend_from_slice(payload);
+}
+
+/**
+ * Wait for the 101 to be sent, and read the start of the client's HTTP/2 connection.
+ *
+ * The settings from the HTTP2-Settings header are merged into the client's first SETTINGS frame,
+ * ahead of its own so that those take precedence, and the request which asked for the upgrade
+ * follows it as stream 1, which is how the HTTP/2 server then sees it.
+ *
+ * # Returns
+ * The connection, to be served as HTTP/2.
+ */
+pub async fn upgraded_io(upgrade: PendingUpgrade) -> std::io::Result<TokioIo<Prefixed<TokioIo<hyper::upgrade::Upgraded>>>>{
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+    let mut io = TokioIo::new(upgrade.on_upgrade.await.map_err(std::io::Error::other)?);
+    let mut preface = [0u8; PREFACE.len()];
+    io.read_exact(&mut preface).await?;
+    if preface != PREFACE{
+        return Err(invalid("client did not send the HTTP/2 preface"));
+    }
+    let mut header = [0u8; FRAME_HEADER_LENGTH];
+    io.read_exact(&mut header).await?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if header[3] != SETTINGS_FRAME || header[4] & ACK != 0 || header[5..] != [0; 4]{
+        return Err(invalid("client did not start with a SETTINGS frame"));
+    }
+    if !length.is_multiple_of(SETTING_LENGTH) || upgrade.settings.len() + length > MAX_FRAME_SIZE{
+        return Err(invalid("SETTINGS frame is malformed or too large"));
+    }
+    let mut settings = upgrade.settings;
+    settings.resize(settings.len() + length, 0);
+    let start = settings.len() - length;
+    io.read_exact(&mut settings[start..]).await?;
+
+    let mut prefix = PREFACE.to_vec();
+    encode_frame(&mut prefix, SETTINGS_FRAME, 0, 0, &settings);
+    encode_frame(&mut prefix, HEADERS_FRAME, END_HEADERS | END_STREAM, 1, &upgrade.header_block);
+    Ok(TokioIo::new(Prefixed{ prefix: Bytes::from(prefix), inner: io }))
+}
+
+/**
+ * Build the HTTP/2 server for upgraded connections, with the same settings as the connections
+ * which start as HTTP/2.
+ *
+ * # Arguments
+ * * `config` - The HTTP/2 settings.
+ */
+pub fn builder(config: &Http2Config) -> http2::Builder<TokioExecutor>{
+    let mut builder = http2::Builder::new(TokioExecutor::new());
+    builder.timer(TokioTimer::new())
+        .max_concurrent_streams(config.max_concurrent_streams)
+        .initial_stream_window_size(config.initial_stream_window_size)
+        .initial_connection_window_size(config.initial_connection_window_size)
+        .adaptive_window(config.adaptive_window)
+        .max_frame_size(config.max_frame_size);
+    builder
+}
+
+/**
+ * A connection which reads some bytes of its own before those of the client.
+ */
+pub struct Prefixed<I>{
+    prefix: Bytes,
+    inner: I
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Prefixed<I>{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>>{
+        let this = self.get_mut();
+        if this.prefix.is_empty(){
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let length = this.prefix.len().min(buf.remaining());
+        buf.put_slice(&this.prefix.split_to(length));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for Prefixed<I>{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>>{
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[std::io::IoSlice<'_>]) -> Poll<std::io::Result<usize>>{
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool{
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>{
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>{
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn upgrade(method: &str, extra: &[(&str, &str)]) -> Request<()>{
+        let mut builder = Request::builder()
+            .method(method)
+            .uri("/a%20b?x=1")
+            .header(HOST, "example.com")
+            .header(CONNECTION, "Upgrade, HTTP2-Settings")
+            .header(UPGRADE, "h2c")
+            .header("HTTP2-Settings", "AAMAAABkAAQAoAAAAAIAAAAA");
+        for (name, value) in extra{
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn accepts_upgrade_without_body(){
+        let (settings, block) = upgrade_request(&upgrade("GET", &[("accept", "*/*")])).unwrap();
+        assert_eq!(settings, [0, 3, 0, 0, 0, 100, 0, 4, 0, 160, 0, 0, 0, 2, 0, 0, 0, 0]);
+        let mut expected = Vec::new();
+        encode_literal(&mut expected, b":method", b"GET");
+        encode_literal(&mut expected, b":scheme", b"http");
+        encode_literal(&mut expected, b":authority", b"example.com");
+        encode_literal(&mut expected, b":path", b"/a%20b?x=1");
+        encode_literal(&mut expected, b"accept", b"*/*");
+        assert_eq!(block, expected);
+    }
+
+    #[test]
+    fn refuses_upgrades_it_cannot_serve(){
+        assert!(upgrade_request(&upgrade("POST", &[("content-length", "5")])).is_none());
+        assert!(upgrade_request(&upgrade("POST", &[("transfer-encoding", "chunked")])).is_none());
+        assert!(upgrade_request(&upgrade("GET", &[("http2-settings", "AAMAAABk")])).is_none());
+
+        let mut request = upgrade("GET", &[]);
+        request.headers_mut().insert("http2-settings", "AAMAAA".parse().unwrap());
+        assert!(upgrade_request(&request).is_none(), "settings must be whole");
+
+        let mut request = upgrade("GET", &[]);
+        request.headers_mut().insert(CONNECTION, "Upgrade".parse().unwrap());
+        assert!(upgrade_request(&request).is_none(), "HTTP2-Settings must be a connection header");
+
+        let mut request = upgrade("GET", &[]);
+        *request.version_mut() = Version::HTTP_10;
+        assert!(upgrade_request(&request).is_none());
+    }
+
+    #[test]
+    fn encodes_lengths_with_seven_bit_prefix(){
+        let encoded = |value| {
+            let mut block = Vec::new();
+            encode_integer(&mut block, value);
+            block
+        };
+        assert_eq!(encoded(10), [10]);
+        assert_eq!(encoded(126), [126]);
+        assert_eq!(encoded(127), [127, 0]);
+        // 1337 - 127 = 58 + 9 * 128
+        assert_eq!(encoded(1337), [127, 186, 9]);
+    }
+
+    #[tokio::test]
+    async fn prefix_is_read_before_connection(){
+        let (client, server) = tokio::io::duplex(64);
+        let mut prefixed = Prefixed{ prefix: Bytes::from_static(b"abc"), inner: server };
+        let mut client = client;
+        tokio::io::AsyncWriteExt::write_all(&mut client, b"def").await.unwrap();
+        drop(client);
+        let mut read = Vec::new();
+        prefixed.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"abcdef");
+    }
+}
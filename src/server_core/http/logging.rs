@@ -0,0 +1,172 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
+
+use base64::Engine;
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::{Request, Response};
+use tower::{Layer, Service};
+
+use crate::access_log::{AccessLog, HttpEntry};
+use crate::metrics::Metrics;
+use crate::router::HandlerFuture;
+use crate::server_core::connection_info::ConnectionInfo;
+
+/**
+ * A tower Layer which records every request in the metrics, and writes a line for it to the
+ * access log.
+ *
+ * Both happen once the response body has been sent, or abandoned, so that they record the bytes
+ * which were actually sent and the full time taken.
+ */
+pub struct RequestLogLayer{
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Arc<Metrics>,
+    info: ConnectionInfo
+}
+
+impl RequestLogLayer{
+    /**
+     * # Arguments
+     * * `access_log` - The access log, or None to log nothing.
+     * * `metrics` - The metrics to record requests in.
+     * * `info` - What is known about the connection.
+     */
+    pub fn new(access_log: Option<Arc<AccessLog>>, metrics: Arc<Metrics>, info: ConnectionInfo) -> Self{
+        Self{ access_log, metrics, info }
+    }
+}
+
+impl<S> Layer<S> for RequestLogLayer{
+    type Service = RequestLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service{
+        RequestLogService{
+            inner,
+            access_log: self.access_log.clone(),
+            metrics: Arc::clone(&self.metrics),
+            info: self.info.clone()
+        }
+    }
+}
+
+/**
+ * The tower Service produced by RequestLogLayer.
+ */
+#[derive(Clone)]
+pub struct RequestLogService<S>{
+    inner: S,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Arc<Metrics>,
+    info: ConnectionInfo
+}
+
+impl<S, B> Service<Request<B>> for RequestLogService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody<Bytes, std::io::Error>>, Error = hyper::Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody<Bytes, std::io::Error>>;
+    type Error = hyper::Error;
+    type Future = HandlerFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>{
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future{
+        let access_log = self.access_log.clone();
+        let metrics = Arc::clone(&self.metrics);
+        let header = |name: &str| request.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let entry = HttpEntry{
+            peer: self.info.peer_addr.ip(),
+            user: header("Authorization").as_deref().and_then(basic_auth_user),
+            time: SystemTime::now(),
+            method: request.method().to_string(),
+            target: request.uri().path_and_query().map(|target| target.to_string()).unwrap_or_default(),
+            version: format!("{:?}", request.version()),
+            status: 0,
+            bytes: 0,
+            duration: Default::default(),
+            referer: header("Referer"),
+            user_agent: header("User-Agent")
+        };
+        let started = Instant::now();
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            let entry = HttpEntry{ status: response.status().as_u16(), ..entry };
+            Ok(response.map(|body| BodyExt::boxed(LoggedBody{
+                inner: body,
+                access_log,
+                metrics,
+                entry,
+                started
+            })))
+        })
+    }
+}
+
+/**
+ * A response body which counts the bytes sent, and logs its request when dropped.
+ */
+struct LoggedBody{
+    inner: BoxBody<Bytes, std::io::Error>,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Arc<Metrics>,
+    entry: HttpEntry,
+    started: Instant
+}
+
+impl Body for LoggedBody{
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>>{
+        let this = self.get_mut();
+        let frame = Pin::new(&mut this.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame{
+            if let Some(data) = frame.data_ref(){
+                this.entry.bytes += data.len() as u64;
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool{
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint{
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody{
+    fn drop(&mut self){
+        self.entry.duration = self.started.elapsed();
+        self.metrics.record_http_request(&self.entry.method, self.entry.status, self.entry.bytes, self.entry.duration);
+        if let Some(access_log) = &self.access_log{
+            access_log.log_http(&self.entry);
+        }
+    }
+}
+
+/**
+ * Read the username from a Basic Authorization header.
+ */
+fn basic_auth_user(authorization: &str) -> Option<String>{
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic"){
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    decoded.split_once(':').map(|(user, _)| user.to_string())
+}
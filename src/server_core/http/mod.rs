@@ -0,0 +1,343 @@
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::{body::Bytes, Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use tower::util::BoxCloneService;
+use tower::Layer;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::Instrument;
+use crate::access_log::AccessLog;
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, MiddlewareLayer};
+use crate::router::{HandlerFuture, RequestBody, Router};
+use crate::server_core::{self, full_box_body};
+use crate::server_core::connection_info::{ConnectionInfo, TlsInfo};
+use crate::server_core::file_body::FileBody;
+use crate::server_core::mime::MimeRegistry;
+use crate::server_utils::{self, Config, Http2Config};
+use crate::shutdown_utils::ShutdownHelper;
+use conditional::{Precondition, Validators};
+use connection::{ConnectionActivity, ConnectionLayer, ConnectionService, TimedIo};
+use h2c::H2cLayer;
+use logging::{RequestLogLayer, RequestLogService};
+use range::RangeRequest;
+
+pub mod autoindex;
+pub mod conditional;
+pub mod connection;
+pub mod h2c;
+pub mod logging;
+pub mod range;
+
+/**
+ * State shared by every HTTP connection.
+ */
+pub struct HttpState{
+    pub config: Arc<Config>,
+    pub mime_types: MimeRegistry,
+    pub middleware: Vec<Arc<dyn Middleware>>,
+    // performs the handshake on connections to the HTTPS listener
+    pub tls: Option<TlsAcceptor>,
+    // negotiates HTTP/1.1 or HTTP/2 on each connection
+    pub protocols: auto::Builder<TokioExecutor>,
+    pub access_log: Option<Arc<AccessLog>>,
+    pub metrics: Arc<Metrics>
+}
+
+fn not_found(request_path: &str) -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(full_box_body(format!("File {} not found", request_path)))
+        .unwrap()
+}
+
+fn internal_server_error() -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(full_box_body("Internal server error"))
+        .unwrap()
+}
+
+async fn get_handler(request: Request<RequestBody>, state: Arc<HttpState>) -> Result<Response<BoxBody<Bytes, std::io::Error>>, hyper::Error>{
+    let request_path = request.uri().path();
+    // read the file and return it as the response body
+
+    let config = &state.config;
+    let (status, static_file) = server_core::process_file_request(&config.document_root, &state.mime_types, request_path).await; 
+    match status{
+        server_utils::FileOpenStatus::DNE if config.autoindex && request_path.ends_with('/') => {
+            // there is no index.html, so list the directory instead
+            match autoindex::directory_listing(&request, &config.document_root).await{
+                Some(response) => Ok(response),
+                None => Ok(not_found(request_path))
+            }
+        },
+        server_utils::FileOpenStatus::DNE => Ok(not_found(request_path)),
+        server_utils::FileOpenStatus::FORBIDDEN => {
+            let response = Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(full_box_body("Forbidden"))
+                .unwrap();
+            Ok(response)
+        },
+        server_utils::FileOpenStatus::ERROR => Ok(internal_server_error()),
+        server_utils::FileOpenStatus::SUCCESS => {
+            let static_file = static_file.unwrap();
+            let length = static_file.metadata.len();
+            let validators = Validators::from_metadata(&static_file.metadata);
+            let content_encoding = static_file.content_encoding.clone();
+            match conditional::evaluate_preconditions(&request, &validators){
+                Precondition::Proceed => {},
+                Precondition::NotModified => return Ok(conditional::not_modified_response(&validators)),
+                Precondition::Failed => return Ok(conditional::precondition_failed_response())
+            }
+            let mut response = match range::requested_ranges(&request, length, &validators){
+                RangeRequest::Full => {
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", static_file.content_type)
+                        .header("Content-Length", length)
+                        .header("Accept-Ranges", "bytes")
+                        .body(FileBody::new(static_file.file, length).boxed())
+                        .unwrap()
+                },
+                RangeRequest::Partial(ranges) => match range::partial_response(static_file, ranges).await{
+                    Ok(response) => response,
+                    Err(_) => return Ok(internal_server_error())
+                },
+                RangeRequest::Unsatisfiable => range::unsatisfiable_response(length)
+            };
+            validators.insert_headers(response.headers_mut());
+            if let Some(encoding) = content_encoding.and_then(|encoding| encoding.parse().ok()){
+                response.headers_mut().insert("Content-Encoding", encoding);
+            }
+            Ok(response)
+        }
+    }
+}
+
+/**
+ * Build the service which routes each request to its handler.
+ * 
+ * Static files are served for every path which no other route claims.
+ * 
+ * # Arguments
+ * * `state` - State shared by every HTTP connection.
+ */
+pub fn router(state: Arc<HttpState>) -> impl Fn(Request<RequestBody>) -> HandlerFuture + Clone{
+    Router::new()
+        .get("/*path", move |request| get_handler(request, Arc::clone(&state)))
+        .into_service()
+}
+
+/**
+ * The router wrapped in the configured middleware.
+ */
+pub type RoutedService = BoxCloneService<Request<RequestBody>, Response<BoxBody<Bytes, std::io::Error>>, hyper::Error>;
+
+/**
+ * Build the service for a connection: the router, wrapped in the configured middleware.
+ * 
+ * # Arguments
+ * * `state` - State shared by every HTTP connection.
+ */
+pub fn service(state: Arc<HttpState>) -> RoutedService{
+    let mut service = BoxCloneService::new(tower::service_fn(router(Arc::clone(&state))));
+    // wrap from the innermost out, so the first middleware listed runs first
+    for middleware in state.middleware.iter().rev(){
+        service = BoxCloneService::new(MiddlewareLayer::new(Arc::clone(middleware)).layer(service));
+    }
+    service
+}
+
+/**
+ * Build the connection builder which serves HTTP/1.1, and HTTP/2 unless it is disabled.
+ * 
+ * The protocol is chosen from the first bytes the client sends, so HTTP/2 is used over TLS when
+ * ALPN selected "h2", and over cleartext when the client starts with the HTTP/2 preface (prior
+ * knowledge). Cleartext HTTP/1.1 connections can also switch with "Upgrade: h2c", which is
+ * handled by H2cLayer.
+ * 
+ * # Arguments
+ * * `config` - The HTTP/2 settings.
+ */
+pub fn connection_builder(config: &Http2Config) -> auto::Builder<TokioExecutor>{
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    // the header read timeout is enforced by ConnectionActivity, as hyper's also covers the wait
+    // between requests, which is limited by the idle timeout instead
+    builder.http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(None);
+    if !config.enabled{
+        return builder.http1_only();
+    }
+    builder.http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.max_concurrent_streams)
+        .initial_stream_window_size(config.initial_stream_window_size)
+        .initial_connection_window_size(config.initial_connection_window_size)
+        .adaptive_window(config.adaptive_window)
+        .max_frame_size(config.max_frame_size);
+    builder
+}
+
+/**
+ * The protocols offered with ALPN during the TLS handshake, most preferred first.
+ * 
+ * # Arguments
+ * * `config` - The HTTP/2 settings.
+ */
+pub fn alpn_protocols(config: &Http2Config) -> Vec<Vec<u8>>{
+    if config.enabled{
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    }else{
+        vec![b"http/1.1".to_vec()]
+    }
+}
+
+/**
+ * The service for the requests of a connection: the router, wrapped in the connection limits.
+ */
+fn connection_service(state: &Arc<HttpState>, activity: &Arc<ConnectionActivity>, info: &ConnectionInfo) -> ConnectionService<RoutedService>{
+    ConnectionLayer::new(Arc::clone(activity), info.clone(), Arc::clone(&state.metrics), &state.config.connections)
+        .layer(service(Arc::clone(state)))
+}
+
+/**
+ * Wrap a service in the request log, outermost so that requests answered by the connection
+ * limits are logged too.
+ */
+fn logged<S>(state: &HttpState, info: &ConnectionInfo, service: S) -> RequestLogService<S>{
+    RequestLogLayer::new(state.access_log.clone(), Arc::clone(&state.metrics), info.clone()).layer(service)
+}
+
+/**
+ * Serve HTTP on a connection until the client closes it, or it is closed for being idle or
+ * reaching its request limit.
+ * 
+ * # Arguments
+ * * `io` - The connection, either plain TCP or already through the TLS handshake.
+ * * `info` - What is known about the connection, which is added to every request.
+ * * `state` - State shared by every HTTP connection.
+ */
+async fn serve_connection<I>(io: I, info: ConnectionInfo, state: Arc<HttpState>) where I: AsyncRead + AsyncWrite + Unpin + Send + 'static{
+    let limits = &state.config.connections;
+    let activity = Arc::new(ConnectionActivity::new(limits));
+    let io = TokioIo::new(TimedIo::new(io, Arc::clone(&activity), limits));
+    // over TLS, clients choose HTTP/2 with ALPN instead of upgrading
+    if !state.config.http2.enabled || info.tls.is_some(){
+        let service = logged(&state, &info, connection_service(&state, &activity, &info));
+        let connection = pin!(state.protocols.serve_connection(io, TowerToHyperService::new(service)));
+        drive(connection, |connection| connection.graceful_shutdown(), &activity).await;
+        return;
+    }
+
+    let (upgrades, mut upgrade) = H2cLayer::new();
+    let service = logged(&state, &info, upgrades.layer(connection_service(&state, &activity, &info)));
+    let connection = pin!(state.protocols.serve_connection_with_upgrades(io, TowerToHyperService::new(service)));
+    if !drive(connection, |connection| connection.graceful_shutdown(), &activity).await{
+        return;
+    }
+    // the HTTP/1.1 connection ended, either closed or handed over to HTTP/2
+    let Ok(upgrade) = upgrade.try_recv() else {
+        return;
+    };
+    let header_read_timeout = Duration::from_secs(limits.header_read_timeout);
+    let io = match tokio::time::timeout(header_read_timeout, h2c::upgraded_io(upgrade)).await{
+        Ok(Ok(io)) => io,
+        Ok(Err(e)) => return tracing::debug!(error = %e, "could not upgrade to HTTP/2"),
+        Err(_) => return tracing::debug!("timed out waiting for HTTP/2 after upgrading")
+    };
+    tracing::debug!("upgraded to HTTP/2");
+    let service = logged(&state, &info, connection_service(&state, &activity, &info));
+    let connection = pin!(h2c::builder(&state.config.http2).serve_connection(io, TowerToHyperService::new(service)));
+    drive(connection, |connection| connection.graceful_shutdown(), &activity).await;
+}
+
+/**
+ * Poll a connection until it ends, shutting it down gracefully once it should be closed.
+ *
+ * # Arguments
+ * * `connection` - The connection being served.
+ * * `graceful_shutdown` - Asks the connection to finish the requests in progress, then close.
+ * * `activity` - The activity of the connection, which decides when it should be closed.
+ *
+ * # Returns
+ * Whether the connection ended by itself, rather than being dropped for not closing in time.
+ */
+async fn drive<C, E>(mut connection: Pin<&mut C>, graceful_shutdown: impl Fn(Pin<&mut C>), activity: &ConnectionActivity) -> bool
+where
+    C: Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    let mut closing = false;
+    loop{
+        tokio::select!{
+            result = connection.as_mut() => {
+                if let Err(e) = result{
+                    tracing::warn!(error = %e, "error serving connection");
+                }
+                return true;
+            },
+            _ = activity.wait_until_closing(), if !closing => {
+                // finish the requests in progress, then close
+                graceful_shutdown(connection.as_mut());
+                closing = true;
+            },
+            _ = activity.wait_until_idle(), if closing => {
+                // the client has not finished closing the connection, so drop it
+                return false;
+            },
+            _ = activity.wait_until_headers_overdue() => {
+                tracing::debug!("timed out reading request headers");
+                return false;
+            }
+        }
+    }
+}
+
+pub fn connection_adaptor(stream: TcpStream, info: ConnectionInfo, shutdown_helper: &mut ShutdownHelper, state: Arc<HttpState>){
+    let handle = shutdown_helper.register(info.peer_addr.ip());
+    let span = tracing::info_span!("http_connection", id = info.id, peer = %info.peer_addr);
+    tokio::spawn(async {
+        serve_connection(stream, info, state).await;
+        handle.send(()).unwrap();
+    }.instrument(span));
+}
+
+/**
+ * Accept a connection to the HTTPS listener, serving HTTP once the TLS handshake completes.
+ * 
+ * # Arguments
+ * * `stream` - The connection.
+ * * `info` - What is known about the connection, completed with the TLS parameters.
+ * * `shutdown_helper` - Tracks the connection, so shutdown can wait for it.
+ * * `state` - State shared by every HTTP connection, which must hold a TLS acceptor.
+ */
+pub fn tls_connection_adaptor(stream: TcpStream, mut info: ConnectionInfo, shutdown_helper: &mut ShutdownHelper, state: Arc<HttpState>){
+    let acceptor = state.tls.clone().expect("HTTPS listener started without a TLS acceptor");
+    let handle = shutdown_helper.register(info.peer_addr.ip());
+    // the handshake is held to the same limit as reading the headers of a request
+    let handshake_timeout = Duration::from_secs(state.config.connections.header_read_timeout);
+    let span = tracing::info_span!("https_connection", id = info.id, peer = %info.peer_addr);
+    tokio::spawn(async move {
+        match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await{
+            Ok(Ok(stream)) => {
+                info.tls = Some(TlsInfo::from_connection(stream.get_ref().1));
+                tracing::debug!(%info, "completed TLS handshake");
+                serve_connection(stream, info, state).await
+            },
+            Ok(Err(e)) => tracing::debug!(error = %e, "TLS handshake failed"),
+            Err(_) => tracing::debug!("TLS handshake timed out")
+        }
+        handle.send(()).unwrap();
+    }.instrument(span));
+}
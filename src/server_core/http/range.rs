@@ -0,0 +1,341 @@
+use std::io::SeekFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::{stream, StreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, BodyStream, StreamBody};
+use hyper::{body::Bytes, Request, Response, StatusCode};
+use tokio::fs::File;
+use tokio::io::AsyncSeekExt;
+
+use crate::server_core::{full_box_body, StaticFile};
+use crate::server_core::file_body::FileBody;
+use crate::server_core::http::conditional::Validators;
+
+/**
+ * The most ranges which will be honoured in a single request. Requests for more are served whole,
+ * so that a client cannot make the server open the same file thousands of times.
+ */
+const MAX_RANGES: usize = 16;
+
+/**
+ * An inclusive range of bytes within a file.
+ */
+pub struct ByteRange{
+    pub start: u64,
+    pub end: u64
+}
+
+impl ByteRange{
+    fn len(&self) -> u64{
+        self.end - self.start + 1
+    }
+}
+
+/**
+ * The outcome of evaluating the Range header of a request.
+ */
+pub enum RangeRequest{
+    Full,
+    Partial(Vec<ByteRange>),
+    Unsatisfiable
+}
+
+/**
+ * Decide which parts of a file should be sent for a request.
+ *
+ * Honours If-Range, so that a client resuming a download of a file which has since changed
+ * receives the whole new file instead of a piece of it.
+ *
+ * # Arguments
+ * * `request` - The request, whose Range and If-Range headers are evaluated.
+ * * `length` - The length of the file in bytes.
+ * * `validators` - The validators of the file, which If-Range is checked against.
+ */
+pub fn requested_ranges<B>(request: &Request<B>, length: u64, validators: &Validators) -> RangeRequest{
+    let range = match request.headers().get("Range").and_then(|value| value.to_str().ok()){
+        Some(range) => range,
+        None => return RangeRequest::Full
+    };
+    if let Some(if_range) = request.headers().get("If-Range"){
+        let validated = if_range.to_str()
+            .map(|if_range| validators.matches_if_range(if_range))
+            .unwrap_or(false);
+        if !validated{
+            return RangeRequest::Full;
+        }
+    }
+    parse_range(range, length)
+}
+
+/**
+ * Parse the value of a Range header.
+ *
+ * A header which is malformed, uses a unit other than bytes, or asks for too many ranges is
+ * ignored, and the whole file is sent. Ranges which start past the end of the file are dropped,
+ * and if none are left the request cannot be satisfied.
+ *
+ * # Arguments
+ * * `header` - The value of the Range header, such as "bytes=0-499,-500".
+ * * `length` - The length of the file in bytes.
+ */
+pub fn parse_range(header: &str, length: u64) -> RangeRequest{
+    let (unit, specs) = match header.split_once('='){
+        Some(parts) => parts,
+        None => return RangeRequest::Full
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes"){
+        return RangeRequest::Full;
+    }
+    let specs: Vec<&str> = specs.split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES{
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs{
+        let (first, last) = match spec.split_once('-'){
+            Some(parts) => parts,
+            None => return RangeRequest::Full
+        };
+        let range = match (first.trim(), last.trim()){
+            ("", "") => return RangeRequest::Full,
+            ("", suffix) => {
+                // the last `suffix` bytes of the file
+                let suffix = match suffix.parse::<u64>(){
+                    Ok(suffix) => suffix,
+                    Err(_) => return RangeRequest::Full
+                };
+                if suffix == 0 || length == 0{
+                    None
+                }else{
+                    Some(ByteRange{ start: length.saturating_sub(suffix), end: length - 1 })
+                }
+            },
+            (start, end) => {
+                let start = match start.parse::<u64>(){
+                    Ok(start) => start,
+                    Err(_) => return RangeRequest::Full
+                };
+                let end = if end.is_empty(){
+                    u64::MAX
+                }else{
+                    match end.parse::<u64>(){
+                        Ok(end) => end,
+                        Err(_) => return RangeRequest::Full
+                    }
+                };
+                if end < start{
+                    return RangeRequest::Full;
+                }
+                if start >= length{
+                    None
+                }else{
+                    Some(ByteRange{ start, end: end.min(length - 1) })
+                }
+            }
+        };
+        if let Some(range) = range{
+            ranges.push(range);
+        }
+    }
+
+    if ranges.is_empty(){
+        RangeRequest::Unsatisfiable
+    }else{
+        RangeRequest::Partial(ranges)
+    }
+}
+
+/**
+ * Build a 206 response containing the requested ranges of a file.
+ *
+ * A single range is sent as the body directly. Multiple ranges are sent as a
+ * multipart/byteranges body, with each part streamed from its own handle on the file.
+ *
+ * # Arguments
+ * * `static_file` - The file which was requested.
+ * * `ranges` - The satisfiable ranges to send, in the order they were requested.
+ */
+pub async fn partial_response(static_file: StaticFile, ranges: Vec<ByteRange>) -> Result<Response<BoxBody<Bytes, std::io::Error>>, std::io::Error>{
+    let length = static_file.metadata.len();
+    if let [range] = ranges.as_slice(){
+        let mut file = static_file.file;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let response = Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Type", static_file.content_type)
+            .header("Content-Range", format!("bytes {}-{}/{}", range.start, range.end, length))
+            .header("Content-Length", range.len())
+            .header("Accept-Ranges", "bytes")
+            .body(FileBody::new(file, range.len()).boxed())
+            .unwrap();
+        return Ok(response);
+    }
+
+    let boundary = multipart_boundary(&static_file);
+    let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+    let mut content_length = 0;
+    for range in &ranges{
+        let part_header = format!(
+            "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {start}-{end}/{length}\r\n\r\n",
+            content_type = static_file.content_type,
+            start = range.start,
+            end = range.end
+        );
+        let mut file = File::open(&static_file.path).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+
+        content_length += part_header.len() as u64 + range.len();
+        parts.push(full_box_body(part_header));
+        parts.push(FileBody::new(file, range.len()).boxed());
+    }
+    let closing = format!("\r\n--{boundary}--\r\n");
+    content_length += closing.len() as u64;
+    parts.push(full_box_body(closing));
+
+    // stream each part one after the other
+    let body = StreamBody::new(stream::iter(parts.into_iter().map(BodyStream::new)).flatten());
+    let response = Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header("Content-Type", format!("multipart/byteranges; boundary={boundary}"))
+        .header("Content-Length", content_length)
+        .header("Accept-Ranges", "bytes")
+        .body(BodyExt::boxed(body))
+        .unwrap();
+    Ok(response)
+}
+
+/**
+ * Build the 416 response sent when none of the requested ranges overlap the file.
+ *
+ * # Arguments
+ * * `length` - The length of the file in bytes.
+ */
+pub fn unsatisfiable_response(length: u64) -> Response<BoxBody<Bytes, std::io::Error>>{
+    Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header("Content-Range", format!("bytes */{length}"))
+        .header("Accept-Ranges", "bytes")
+        .body(full_box_body("Requested range not satisfiable"))
+        .unwrap()
+}
+
+fn multipart_boundary(static_file: &StaticFile) -> String{
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let seed = format!("{}:{}", static_file.path.display(), now);
+    sha256::digest(seed)[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests{
+    use std::time::Duration;
+
+    use super::*;
+
+    /**
+     * The ranges a header asks for, as (start, end) pairs, or None if it is not a partial request.
+     */
+    fn partial(header: &str, length: u64) -> Option<Vec<(u64, u64)>>{
+        match parse_range(header, length){
+            RangeRequest::Partial(ranges) => Some(ranges.iter().map(|range| (range.start, range.end)).collect()),
+            RangeRequest::Full | RangeRequest::Unsatisfiable => None
+        }
+    }
+
+    fn is_full(header: &str, length: u64) -> bool{
+        matches!(parse_range(header, length), RangeRequest::Full)
+    }
+
+    fn is_unsatisfiable(header: &str, length: u64) -> bool{
+        matches!(parse_range(header, length), RangeRequest::Unsatisfiable)
+    }
+
+    #[test]
+    fn closed_ranges(){
+        assert_eq!(partial("bytes=0-499", 1000), Some(vec![(0, 499)]));
+        assert_eq!(partial("bytes=500-500", 1000), Some(vec![(500, 500)]));
+        assert_eq!(partial(" Bytes = 0-0 , 10-19 ", 1000), Some(vec![(0, 0), (10, 19)]));
+    }
+
+    #[test]
+    fn suffix_ranges(){
+        assert_eq!(partial("bytes=-500", 1000), Some(vec![(500, 999)]));
+        // a suffix longer than the file is the whole file
+        assert_eq!(partial("bytes=-5000", 1000), Some(vec![(0, 999)]));
+        assert!(is_unsatisfiable("bytes=-0", 1000));
+        assert!(is_unsatisfiable("bytes=-10", 0));
+    }
+
+    #[test]
+    fn open_ended_ranges(){
+        assert_eq!(partial("bytes=900-", 1000), Some(vec![(900, 999)]));
+        assert_eq!(partial("bytes=0-", 1000), Some(vec![(0, 999)]));
+    }
+
+    #[test]
+    fn end_past_the_end_of_the_file_is_clamped(){
+        assert_eq!(partial("bytes=990-2000", 1000), Some(vec![(990, 999)]));
+        assert_eq!(partial("bytes=0-18446744073709551615", 1000), Some(vec![(0, 999)]));
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_dropped(){
+        assert_eq!(partial("bytes=1000-1100,0-9", 1000), Some(vec![(0, 9)]));
+        assert!(is_unsatisfiable("bytes=1000-", 1000));
+        assert!(is_unsatisfiable("bytes=1000-1100,2000-2100", 1000));
+        assert!(is_unsatisfiable("bytes=0-10", 0));
+    }
+
+    #[test]
+    fn end_before_start_is_ignored(){
+        assert!(is_full("bytes=500-400", 1000));
+        assert!(is_full("bytes=0-9,500-400", 1000));
+    }
+
+    #[test]
+    fn too_many_ranges_are_ignored(){
+        let ranges = |count: u64| (0..count).map(|index| format!("{}-{}", index * 10, index * 10 + 1)).collect::<Vec<_>>().join(",");
+        assert_eq!(partial(&format!("bytes={}", ranges(MAX_RANGES as u64)), 1000).map(|ranges| ranges.len()), Some(MAX_RANGES));
+        assert!(is_full(&format!("bytes={}", ranges(MAX_RANGES as u64 + 1)), 1000));
+    }
+
+    #[test]
+    fn other_units_and_malformed_headers_are_ignored(){
+        assert!(is_full("items=0-9", 1000));
+        assert!(is_full("0-9", 1000));
+        assert!(is_full("bytes=", 1000));
+        assert!(is_full("bytes=-", 1000));
+        assert!(is_full("bytes=5", 1000));
+        assert!(is_full("bytes=a-9", 1000));
+        assert!(is_full("bytes=0-b", 1000));
+        assert!(is_full("bytes=--5", 1000));
+    }
+
+    #[test]
+    fn if_range_decides_between_partial_and_full(){
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let validators = Validators{ etag: "\"abc\"".to_string(), last_modified: Some(modified) };
+        let request = |if_range: Option<&str>| {
+            let mut builder = Request::builder().header("Range", "bytes=0-9");
+            if let Some(if_range) = if_range{
+                builder = builder.header("If-Range", if_range);
+            }
+            builder.body(()).unwrap()
+        };
+        let is_partial = |if_range: Option<&str>| matches!(requested_ranges(&request(if_range), 100, &validators), RangeRequest::Partial(_));
+        assert!(is_partial(None));
+        assert!(is_partial(Some("\"abc\"")));
+        assert!(is_partial(Some(&httpdate::fmt_http_date(modified))));
+        assert!(!is_partial(Some("\"other\"")));
+        assert!(!is_partial(Some("W/\"abc\"")));
+        assert!(!is_partial(Some(&httpdate::fmt_http_date(modified - Duration::from_secs(1)))));
+        let without_range = Request::builder().body(()).unwrap();
+        assert!(matches!(requested_ranges(&without_range, 100, &validators), RangeRequest::Full));
+    }
+}
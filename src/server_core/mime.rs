@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::server_utils::Config;
+
+/**
+ * Extensions and the media types they are served as.
+ *
+ * Compound extensions such as "tar.gz" take precedence over their last extension.
+ */
+const BUILT_IN_TYPES: &[(&str, &str)] = &[
+    // text
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("ics", "text/calendar"),
+    ("vtt", "text/vtt"),
+    ("xml", "text/xml"),
+    ("yaml", "text/yaml"),
+    ("yml", "text/yaml"),
+    ("toml", "text/plain"),
+    ("rs", "text/plain"),
+    // application
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("tar.gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("bz2", "application/x-bzip2"),
+    ("tar.bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("tar.xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("tar.zst", "application/zstd"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("jar", "application/java-archive"),
+    ("deb", "application/vnd.debian.binary-package"),
+    ("rpm", "application/x-rpm"),
+    ("dmg", "application/x-apple-diskimage"),
+    ("iso", "application/x-iso9660-image"),
+    ("exe", "application/vnd.microsoft.portable-executable"),
+    ("msi", "application/x-msi"),
+    ("bin", "application/octet-stream"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("epub", "application/epub+zip"),
+    // images
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("svg", "image/svg+xml"),
+    ("svgz", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    // audio
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("aac", "audio/aac"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("weba", "audio/webm"),
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    // video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"),
+    ("avi", "video/x-msvideo"),
+    ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),
+    ("ts", "video/mp2t"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    // fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject")
+];
+
+/**
+ * Extensions which mean the file is compressed, and the Content-Encoding they are served with.
+ *
+ * "page.svg.gz" is served as image/svg+xml with gzip encoding, while "page.gz" is application/gzip.
+ */
+const BUILT_IN_ENCODINGS: &[(&str, &str)] = &[
+    ("gz", "gzip"),
+    ("br", "br"),
+    ("zst", "zstd")
+];
+
+/**
+ * The Content-Type, and Content-Encoding if any, a file is served with.
+ */
+pub struct ContentType{
+    pub mime: String,
+    pub encoding: Option<String>
+}
+
+/**
+ * Maps file extensions to media types.
+ */
+pub struct MimeRegistry{
+    types: HashMap<String, String>,
+    encodings: HashMap<String, String>
+}
+
+impl MimeRegistry{
+    /**
+     * Create a registry containing the built-in table.
+     */
+    pub fn new() -> Self{
+        let types = BUILT_IN_TYPES.iter()
+            .map(|(extension, mime)| (extension.to_string(), mime.to_string()))
+            .collect();
+        let encodings = BUILT_IN_ENCODINGS.iter()
+            .map(|(extension, encoding)| (extension.to_string(), encoding.to_string()))
+            .collect();
+        Self{ types, encodings }
+    }
+
+    /**
+     * Create the registry described by the server configuration.
+     *
+     * The built-in table is extended, and overridden, by the mime_types_file if one is configured.
+     *
+     * # Arguments
+     * * `config` - The server configuration.
+     */
+    pub fn from_config(config: &Config) -> std::io::Result<Self>{
+        let mut registry = Self::new();
+        if let Some(path) = &config.mime_types_file{
+            registry.load_overrides(&std::fs::read_to_string(path)?);
+        }
+        Ok(registry)
+    }
+
+    /**
+     * Add the entries of a file in the format of /etc/mime.types.
+     *
+     * Each line holds a media type followed by its extensions, and "#" starts a comment.
+     *
+     * # Arguments
+     * * `contents` - The contents of the file.
+     */
+    pub fn load_overrides(&mut self, contents: &str){
+        for line in contents.lines(){
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            let mime = match fields.next(){
+                Some(mime) => mime,
+                None => continue
+            };
+            for extension in fields{
+                let extension = extension.trim_start_matches('.').to_lowercase();
+                self.types.insert(extension, mime.to_string());
+            }
+        }
+    }
+
+    /**
+     * Find the content type of a file from its name.
+     *
+     * The longest known extension wins, so "a.tar.gz" is application/gzip rather than being
+     * treated as a gzip encoded tarball. Otherwise, a compression extension on a file whose inner
+     * extension is known is served as that type with a Content-Encoding.
+     *
+     * # Arguments
+     * * `path` - The path of the file.
+     *
+     * # Returns
+     * The content type, or None if no extension of the file is known.
+     */
+    pub fn lookup(&self, path: &Path) -> Option<ContentType>{
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        let (stem, last) = name.rsplit_once('.')?;
+
+        // compound extensions, longest first
+        for (index, _) in stem.match_indices('.'){
+            if let Some(mime) = self.types.get(&name[index + 1..]){
+                return Some(ContentType{ mime: mime.clone(), encoding: None });
+            }
+        }
+        if let Some(encoding) = self.encodings.get(last){
+            if let Some(inner) = self.lookup(Path::new(stem)){
+                if inner.encoding.is_none(){
+                    return Some(ContentType{ mime: inner.mime, encoding: Some(encoding.clone()) });
+                }
+            }
+        }
+        self.types.get(last).map(|mime| ContentType{ mime: mime.clone(), encoding: None })
+    }
+}
+
+/**
+ * Guess the media type of a file from its first bytes.
+ *
+ * Used for files whose name has no known extension.
+ *
+ * # Arguments
+ * * `prefix` - The first bytes of the file, 512 is plenty.
+ */
+pub fn sniff(prefix: &[u8]) -> &'static str{
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"\0asm", "application/wasm"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"\x7fELF", "application/octet-stream")
+    ];
+    for (signature, mime) in SIGNATURES{
+        if prefix.starts_with(signature){
+            return mime;
+        }
+    }
+    if prefix.len() >= 12 && &prefix[..4] == b"RIFF" && &prefix[8..12] == b"WEBP"{
+        return "image/webp";
+    }
+    if prefix.len() >= 8 && &prefix[4..8] == b"ftyp"{
+        return "video/mp4";
+    }
+
+    let text = match std::str::from_utf8(prefix){
+        Ok(text) => text,
+        // the prefix may end part way through a character
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&prefix[..e.valid_up_to()]).unwrap(),
+        Err(_) => return "application/octet-stream"
+    };
+    if text.chars().any(|c| c.is_control() && !c.is_ascii_whitespace()){
+        return "application/octet-stream";
+    }
+    let start = text.trim_start().to_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html"){
+        "text/html"
+    }else if start.starts_with("<?xml"){
+        "text/xml"
+    }else{
+        "text/plain"
+    }
+}
+
+/**
+ * Add the charset parameter to text types, which are always served as UTF-8.
+ *
+ * # Arguments
+ * * `mime` - The media type.
+ */
+pub fn with_charset(mime: &str) -> String{
+    if mime.starts_with("text/") && !mime.contains("charset"){
+        format!("{mime}; charset=utf-8")
+    }else{
+        mime.to_string()
+    }
+}
@@ -1,24 +1,80 @@
 
-use std::borrow::Borrow;
+use std::collections::VecDeque;
 use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-use async_std::fs::File;
-use async_std::io::ReadExt;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Full};
 use hyper::body::Bytes;
-use hyper::{Request, Response};
-use hyper_util::server::graceful::GracefulShutdown;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
 
 
-use crate::server_utils::FileOpenStatus;
+use connection_info::ConnectionInfo;
+use crate::server_utils::{AddressFamily, ConnectionLimitsConfig, FileOpenStatus, LimitPolicy, ListenConfig};
+use crate::server_utils::paths::resolve_under_root;
+use mime::{ContentType, MimeRegistry};
 use crate::shutdown_utils::ShutdownHelper;
 
+pub mod admin;
 pub mod http;
 pub mod ftp;
+pub mod file_body;
+pub mod mime;
+pub mod tls;
+pub mod connection_info;
 
+/**
+ * A file which was found for a request, and is ready to be streamed.
+ */
+pub struct StaticFile{
+    pub file: File,
+    pub path: PathBuf,
+    pub metadata: std::fs::Metadata,
+    pub content_type: String,
+    pub content_encoding: Option<String>
+}
+
+
+/**
+ * Bind a listener to a port of the configured address.
+ * 
+ * A dual-stack listener is an IPv6 socket which also accepts IPv4 connections, whose peers appear
+ * as IPv4-mapped IPv6 addresses.
+ * 
+ * # Arguments
+ * * `listen` - The address and family to listen on.
+ * * `port` - The port to listen on.
+ */
+pub fn bind_listener(listen: &ListenConfig, port: u16) -> std::io::Result<TcpListener>{
+    listen.check().map_err(|message| std::io::Error::new(std::io::ErrorKind::InvalidInput, message))?;
+    let address = SocketAddr::new(listen.address(), port);
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    if address.is_ipv6(){
+        socket.set_only_v6(listen.family == AddressFamily::Ipv6)?;
+    }
+    // as tokio does, so a restarted server can bind while old connections are in TIME_WAIT
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/**
+ * Check whether one more connection from an address fits within the connection limits.
+ */
+fn within_limits(limits: &ConnectionLimitsConfig, shutdown_helper: &ShutdownHelper, peer: IpAddr) -> bool{
+    limits.max_connections.is_none_or(|max| shutdown_helper.active() < max)
+        && limits.max_connections_per_ip.is_none_or(|max| shutdown_helper.active_for(peer) < max)
+}
 
 /**
  * Run a server service using the 'service' method to handle incoming requests.
@@ -29,33 +85,80 @@ pub mod ftp;
  * * `listener` - The TCP listener to accept incoming connections.
  * * `shutdown_signal` - A future that resolves when the server should shutdown.
  * * `shutdown_timeout` - The maximum time to wait for the server to shutdown.
+ * * `limits` - Caps on the connections served at once, and what happens to connections over them.
+ * * `shutdown_helper` - Counts the open connections, and waits for them at shutdown.
+ * * `state` - State shared with every connection, such as the server configuration.
  * * `service` - The service function to handle incoming requests.
  */
-pub async fn start_server<T: Future>(
+pub async fn start_server<T: Future, S>(
     listener: TcpListener, 
-    mut shutdown_signal: T,
+    shutdown_signal: T,
     shutdown_timeout: u64,
-    connection_adaptor: fn(TcpStream, &mut ShutdownHelper)
+    limits: ConnectionLimitsConfig,
+    mut shutdown_helper: ShutdownHelper,
+    state: Arc<S>,
+    connection_adaptor: fn(TcpStream, ConnectionInfo, &mut ShutdownHelper, Arc<S>)
 ) -> Result<(), std::io::Error>{
-    let mut shutdown_helper = ShutdownHelper::new();
     let mut shutdown_signal = pin!(shutdown_signal);
+    // connections held until they fit within the limits, oldest first, with when they were queued
+    let mut queued: VecDeque<(TcpStream, ConnectionInfo, Instant)> = VecDeque::new();
+    let queue_timeout = Duration::from_secs(limits.queue_timeout);
+    // serve every queued connection which now fits, skipping those still over a limit, so that
+    // a connection held by its address's limit does not hold up the others
+    let serve_queued = |queued: &mut VecDeque<(TcpStream, ConnectionInfo, Instant)>, shutdown_helper: &mut ShutdownHelper|{
+        while let Some(index) = queued.iter().position(|(_, info, _)| within_limits(&limits, shutdown_helper, info.peer_addr.ip())){
+            let (stream, info, _) = queued.remove(index).unwrap();
+            connection_adaptor(stream, info, shutdown_helper, Arc::clone(&state));
+        }
+    };
     loop{
+        let oldest_deadline = queued.front().map(|(_, _, queued_at)| *queued_at + queue_timeout);
         tokio::select! {
-            Ok((stream, _)) = listener.accept() => connection_adaptor(stream, &mut shutdown_helper),
+            Ok((stream, peer)) = listener.accept() => {
+                let info = match ConnectionInfo::accepted(&stream, peer){
+                    Ok(info) => info,
+                    Err(e) => {
+                        tracing::warn!(%peer, error = %e, "dropping connection");
+                        continue;
+                    }
+                };
+                // those queued first go first, when the same limit held them
+                serve_queued(&mut queued, &mut shutdown_helper);
+                if within_limits(&limits, &shutdown_helper, info.peer_addr.ip()){
+                    connection_adaptor(stream, info, &mut shutdown_helper, Arc::clone(&state));
+                }else if limits.policy == LimitPolicy::Queue && queued.len() < limits.max_queued{
+                    tracing::debug!(%info, "queueing connection, connection limit reached");
+                    queued.push_back((stream, info, Instant::now()));
+                }else{
+                    tracing::warn!(%info, "refusing connection, connection limit reached");
+                }
+            },
+            _ = shutdown_helper.released(), if !queued.is_empty() => {
+                serve_queued(&mut queued, &mut shutdown_helper);
+            },
+            _ = tokio::time::sleep_until(oldest_deadline.unwrap_or_else(Instant::now)), if oldest_deadline.is_some() => {
+                let now = Instant::now();
+                while let Some((_, info, _)) = queued.front().filter(|(_, _, queued_at)| *queued_at + queue_timeout <= now){
+                    tracing::warn!(%info, "dropping queued connection, waited too long for the connection limit");
+                    queued.pop_front();
+                }
+            },
             _ = &mut shutdown_signal => {
-                eprintln!("Starting server shutdown");
+                tracing::info!("starting server shutdown");
+                shutdown_helper.start_draining();
                 break;
             }
         }
     }
     drop(listener);
+    drop(queued);
     // Shutdown the server
     tokio::select! {
         _ = shutdown_helper.shutdown() => {
-            eprintln!("Finished shutdown");
+            tracing::info!("finished shutdown");
         },
         _ = tokio::time::sleep(std::time::Duration::from_secs(shutdown_timeout)) => {
-            eprintln!("Shutdown timeout after {shutdown_timeout} seconds. Closing.")
+            tracing::warn!("shutdown timed out after {shutdown_timeout} seconds, closing")
         }
     }
     Ok(())
@@ -67,52 +170,82 @@ pub async fn start_server<T: Future>(
  * # Arguments
  * * `body` - The data to be converted to bytes.
  */
-pub fn full_box_body<T:Into<Bytes>>(body: T) -> BoxBody<Bytes, hyper::Error>{
+pub fn full_box_body<T:Into<Bytes>>(body: T) -> BoxBody<Bytes, std::io::Error>{
     // Build a Full BoxBody from a data type which can be bytes. 
-    // Converts Infalliable errors to hyper errors.
+    // Converts Infalliable errors to io errors.
     Full::new(body.into())
         .map_err(|n| match n {})
         .boxed()
 }
 
 /**
- * Processes a file request, and returns the status, the buffer, and the Content-Type header.
+ * Processes a file request, and returns the status, and the opened file with its metadata and Content-Type header.
+ * 
+ * The file is not read here, so that it can be streamed into the response body. Only files without
+ * a known extension have their first bytes read, to guess their type.
  * 
  * # Arguments
- * * `path` - The path to the file to be processed.
+ * * `document_root` - The directory which requested paths are resolved under.
+ * * `mime_types` - The registry used to find the Content-Type of the file.
+ * * `path` - The request path of the file to be processed.
  */
-pub async fn process_file_request(path: &str) -> (FileOpenStatus, Option<Vec<u8>>, Option<String>){
+pub async fn process_file_request(document_root: &str, mime_types: &MimeRegistry, path: &str) -> (FileOpenStatus, Option<StaticFile>){
     // if the path is a directory, append index.html
     let path = if path.ends_with('/') {
         format!("{}index.html", path)
     } else {
         path.to_string()
     };
+    let path = match resolve_under_root(document_root, &path).await{
+        Ok(path) => path,
+        Err(status) => return (status, None)
+    };
     
     let mut file = match File::open(&path).await{
         Ok(file) => file,
         Err(_) => {
             // File does not exist
-            return (FileOpenStatus::DNE, None, None);
+            return (FileOpenStatus::DNE, None);
         }
     };
-    let mut buffer = Vec::new();
-    if let Err(_) = file.read_to_end(&mut buffer).await{
-        return (FileOpenStatus::ERROR, None, None);
+    let metadata = match file.metadata().await{
+        Ok(metadata) => metadata,
+        Err(_) => return (FileOpenStatus::ERROR, None)
+    };
+    if !metadata.is_file(){
+        // directories without a trailing slash, devices, etc. are not served
+        return (FileOpenStatus::DNE, None);
     }
-    let content_type = match path.to_lowercase().split('.').last(){
-        Some("html") => Some("text/html".to_string()),
-        Some("css") => Some("text/css".to_string()),
-        Some("js") => Some("text/javascript".to_string()),
-        Some("json") => Some("application/json".to_string()),
-        Some("png") => Some("image/png".to_string()),
-        Some("jpg") => Some("image/jpeg".to_string()),
-        Some("jpeg") => Some("image/jpeg".to_string()),
-        Some("gif") => Some("image/gif".to_string()),
-        Some("svg") => Some("image/svg+xml".to_string()),
-        Some("ico") => Some("image/x-icon".to_string()),
-        Some("pdf") => Some("application/pdf".to_string()),
-        _ => Some("text/plain".to_string())
+    let content_type = match mime_types.lookup(&path){
+        Some(content_type) => content_type,
+        None => match sniff_content_type(&mut file).await{
+            Ok(content_type) => content_type,
+            Err(_) => return (FileOpenStatus::ERROR, None)
+        }
+    };
+    let static_file = StaticFile{
+        file,
+        path,
+        metadata,
+        content_type: mime::with_charset(&content_type.mime),
+        content_encoding: content_type.encoding
     };
-    return (FileOpenStatus::SUCCESS, Some(buffer), content_type);
-}
\ No newline at end of file
+    (FileOpenStatus::SUCCESS, Some(static_file))
+}
+
+/**
+ * Guess the type of a file from its first bytes, and rewind it so it can be sent from the start.
+ */
+async fn sniff_content_type(file: &mut File) -> Result<ContentType, std::io::Error>{
+    let mut prefix = [0u8; 512];
+    let mut filled = 0;
+    while filled < prefix.len(){
+        let bytes_read = file.read(&mut prefix[filled..]).await?;
+        if bytes_read == 0{
+            break;
+        }
+        filled += bytes_read;
+    }
+    file.seek(std::io::SeekFrom::Start(0)).await?;
+    Ok(ContentType{ mime: mime::sniff(&prefix[..filled]).to_string(), encoding: None })
+}
@@ -0,0 +1,283 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::server_utils::{CertificateConfig, TlsConfig};
+
+/**
+ * A configured certificate, and the key pair most recently loaded from its files.
+ */
+#[derive(Debug)]
+struct CertificateEntry{
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    server_names: Vec<String>,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+    // modification times of the certificate and key when they were loaded
+    modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>
+}
+
+/**
+ * Picks the certificate for each handshake from the server name the client asked for.
+ *
+ * Certificates are reloaded when their files change, so renewed certificates are picked up
+ * without restarting the server. Connections which are already open keep their certificate.
+ */
+#[derive(Debug)]
+pub struct CertificateResolver{
+    provider: Arc<CryptoProvider>,
+    entries: Vec<CertificateEntry>
+}
+
+impl CertificateResolver{
+    /**
+     * Load every configured certificate.
+     *
+     * # Arguments
+     * * `certificates` - The certificates, the first of which is the default.
+     * * `provider` - The cryptography used to load the private keys.
+     */
+    pub fn new(certificates: &[CertificateConfig], provider: Arc<CryptoProvider>) -> std::io::Result<Self>{
+        let mut entries = Vec::with_capacity(certificates.len());
+        for certificate in certificates{
+            let cert_path = PathBuf::from(&certificate.cert);
+            let key_path = PathBuf::from(&certificate.key);
+            let modified = (modified_time(&cert_path), modified_time(&key_path));
+            let certified_key = load_certified_key(&cert_path, &key_path, &provider)?;
+            entries.push(CertificateEntry{
+                cert_path,
+                key_path,
+                server_names: certificate.server_names.clone(),
+                certified_key: RwLock::new(Arc::new(certified_key)),
+                modified: Mutex::new(modified)
+            });
+        }
+        Ok(Self{ provider, entries })
+    }
+
+    /**
+     * Reload the certificates whose files have changed since they were last loaded.
+     *
+     * A certificate which fails to load, for example because only one of its files has been
+     * replaced so far, keeps being served as it was and is tried again on the next call.
+     */
+    pub fn reload_changed(&self){
+        for entry in &self.entries{
+            let modified = (modified_time(&entry.cert_path), modified_time(&entry.key_path));
+            let mut last_modified = entry.modified.lock().unwrap();
+            if *last_modified == modified{
+                continue;
+            }
+            match load_certified_key(&entry.cert_path, &entry.key_path, &self.provider){
+                Ok(certified_key) => {
+                    *entry.certified_key.write().unwrap() = Arc::new(certified_key);
+                    *last_modified = modified;
+                    tracing::info!(certificate = %entry.cert_path.display(), "reloaded certificate");
+                },
+                Err(e) => tracing::warn!(certificate = %entry.cert_path.display(), error = %e, "could not reload certificate")
+            }
+        }
+    }
+
+    /**
+     * Pick the certificate for a server name, falling back to the first certificate.
+     *
+     * # Arguments
+     * * `server_name` - The name sent by the client, if any.
+     */
+    fn select(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>>{
+        let entry = server_name
+            .map(|name| name.to_lowercase())
+            .and_then(|name| self.entries.iter().find(|entry| {
+                entry.server_names.iter().any(|pattern| name_matches(pattern, &name))
+            }))
+            .or(self.entries.first())?;
+        Some(Arc::clone(&entry.certified_key.read().unwrap()))
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver{
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>>{
+        self.select(client_hello.server_name())
+    }
+}
+
+/**
+ * Build the acceptor which performs the TLS handshake on new connections.
+ *
+ * This also starts the task which watches the certificate files for changes, so it must be called
+ * from within the runtime.
+ *
+ * # Arguments
+ * * `config` - The settings of the HTTPS listener.
+ * * `alpn_protocols` - The application protocols offered to clients, most preferred first.
+ */
+pub fn acceptor(config: &TlsConfig, alpn_protocols: Vec<Vec<u8>>) -> std::io::Result<TlsAcceptor>{
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let resolver = Arc::new(CertificateResolver::new(&config.certificates, Arc::clone(&provider))?);
+
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(std::io::Error::other)?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::clone(&resolver) as Arc<dyn ResolvesServerCert>);
+    server_config.alpn_protocols = alpn_protocols;
+
+    let interval = Duration::from_secs(config.reload_interval.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop{
+            ticker.tick().await;
+            resolver.reload_changed();
+        }
+    });
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/**
+ * Read a PEM certificate chain and private key, and check that they belong together.
+ *
+ * # Arguments
+ * * `cert_path` - The certificate chain, leaf first.
+ * * `key_path` - The private key of the leaf certificate.
+ * * `provider` - The cryptography used to load the private key.
+ */
+fn load_certified_key(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> std::io::Result<CertifiedKey>{
+    let invalid = |path: &Path, e: &dyn std::fmt::Display| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {e}", path.display()))
+    };
+    let chain = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert_path, &e))?;
+    if chain.is_empty(){
+        return Err(invalid(cert_path, &"no certificates found"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid(key_path, &e))?;
+    CertifiedKey::from_der(chain, key, provider).map_err(|e| invalid(key_path, &e))
+}
+
+/**
+ * Check a server name against a configured name, which may be a wildcard such as
+ * "*.example.com" covering exactly one label.
+ */
+fn name_matches(pattern: &str, name: &str) -> bool{
+    match pattern.strip_prefix("*."){
+        Some(suffix) => name.split_once('.').is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == name
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime>{
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /**
+     * Write a new self signed certificate for the names, returning its DER encoding.
+     */
+    fn write_certificate(directory: &Path, file_name: &str, names: &[&str]) -> (CertificateConfig, Vec<u8>){
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let certified = rcgen::generate_simple_self_signed(names).unwrap();
+        let cert = directory.join(format!("{file_name}.pem"));
+        let key = directory.join(format!("{file_name}.key"));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+        let config = CertificateConfig{
+            cert: cert.display().to_string(),
+            key: key.display().to_string(),
+            server_names: Vec::new()
+        };
+        (config, certified.cert.der().to_vec())
+    }
+
+    fn selected(resolver: &CertificateResolver, server_name: Option<&str>) -> Vec<u8>{
+        resolver.select(server_name).unwrap().cert[0].to_vec()
+    }
+
+    fn provider() -> Arc<CryptoProvider>{
+        Arc::new(rustls::crypto::ring::default_provider())
+    }
+
+    #[test]
+    fn wildcard_covers_exactly_one_label(){
+        assert!(name_matches("*.example.com", "a.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "a.b.example.com"));
+        assert!(!name_matches("*.example.com", ".example.com"));
+        assert!(name_matches("example.com", "example.com"));
+        assert!(!name_matches("example.com", "a.example.com"));
+    }
+
+    #[test]
+    fn picks_certificate_by_server_name(){
+        let directory = tempfile::tempdir().unwrap();
+        let (default, default_der) = write_certificate(directory.path(), "default", &["localhost"]);
+        let (mut exact, exact_der) = write_certificate(directory.path(), "exact", &["example.com"]);
+        exact.server_names = vec!["example.com".to_string()];
+        let (mut wildcard, wildcard_der) = write_certificate(directory.path(), "wildcard", &["*.example.com"]);
+        wildcard.server_names = vec!["*.example.com".to_string()];
+        let resolver = CertificateResolver::new(&[default, exact, wildcard], provider()).unwrap();
+
+        assert_eq!(selected(&resolver, Some("example.com")), exact_der);
+        assert_eq!(selected(&resolver, Some("Example.COM")), exact_der);
+        assert_eq!(selected(&resolver, Some("www.example.com")), wildcard_der);
+        assert_eq!(selected(&resolver, Some("a.b.example.com")), default_der);
+        assert_eq!(selected(&resolver, Some("other.org")), default_der);
+    }
+
+    #[test]
+    fn falls_back_to_first_certificate_without_server_name(){
+        let directory = tempfile::tempdir().unwrap();
+        let (default, default_der) = write_certificate(directory.path(), "default", &["localhost"]);
+        let (mut named, _) = write_certificate(directory.path(), "named", &["example.com"]);
+        named.server_names = vec!["example.com".to_string()];
+        let resolver = CertificateResolver::new(&[default, named], provider()).unwrap();
+
+        assert_eq!(selected(&resolver, None), default_der);
+    }
+
+    #[test]
+    fn reload_picks_up_rewritten_certificate(){
+        let directory = tempfile::tempdir().unwrap();
+        let (config, old_der) = write_certificate(directory.path(), "site", &["localhost"]);
+        let resolver = CertificateResolver::new(std::slice::from_ref(&config), provider()).unwrap();
+        assert_eq!(selected(&resolver, None), old_der);
+
+        // nothing changed, so nothing is reloaded
+        resolver.reload_changed();
+        assert_eq!(selected(&resolver, None), old_der);
+
+        let (_, new_der) = write_certificate(directory.path(), "site", &["localhost"]);
+        // the files may be rewritten within the resolution of their modification times
+        let later = SystemTime::now() + Duration::from_secs(10);
+        for path in [&config.cert, &config.key]{
+            std::fs::File::options().write(true).open(path).unwrap().set_modified(later).unwrap();
+        }
+        resolver.reload_changed();
+        assert_eq!(selected(&resolver, None), new_der);
+    }
+
+    #[test]
+    fn keeps_serving_certificate_which_fails_to_reload(){
+        let directory = tempfile::tempdir().unwrap();
+        let (config, old_der) = write_certificate(directory.path(), "site", &["localhost"]);
+        let resolver = CertificateResolver::new(std::slice::from_ref(&config), provider()).unwrap();
+
+        std::fs::write(&config.key, "not a key").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options().write(true).open(&config.key).unwrap().set_modified(later).unwrap();
+        resolver.reload_changed();
+        assert_eq!(selected(&resolver, None), old_der);
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+/**
+ * The scalar keys which can be overridden from the environment. The variable for a key is its
+ * path in upper case, joined with "_" and prefixed with "WEBSERVER_", such as WEBSERVER_HTTP_PORT
+ * or WEBSERVER_CONNECTIONS_IDLE_TIMEOUT.
+ */
+const OVERRIDABLE_KEYS: &[&str] = &[
+    "listen.family",
+    "listen.address",
+    "http_port",
+    "ftp_control_port",
+    "document_root",
+    "autoindex",
+    "mime_types_file",
+    "log_filter",
+    "ftp_users_directory",
+    "ftp_homes_directory",
+    "ftp_passive.min_port",
+    "ftp_passive.max_port",
+    "ftp_passive.external_address",
+    "ftp_passive.accept_timeout",
+    "tls.port",
+    "tls.reload_interval",
+    "http2.enabled",
+    "http2.max_concurrent_streams",
+    "http2.initial_stream_window_size",
+    "http2.initial_connection_window_size",
+    "http2.adaptive_window",
+    "http2.max_frame_size",
+    "connections.header_read_timeout",
+    "connections.idle_timeout",
+    "connections.request_body_timeout",
+    "connections.write_timeout",
+    "connections.max_requests",
+    "connection_limits.max_connections",
+    "connection_limits.max_connections_per_ip",
+    "connection_limits.policy",
+    "connection_limits.max_queued",
+    "connection_limits.queue_timeout",
+    "access_log.format",
+    "access_log.path",
+    "access_log.max_size",
+    "access_log.rotate_interval",
+    "access_log.keep",
+    "admin.port"
+];
+
+/**
+ * The prefix of every environment variable read by the server.
+ */
+pub const ENV_PREFIX: &str = "WEBSERVER_";
+
+/**
+ * The environment variable holding the path of the configuration file.
+ */
+pub const CONFIG_PATH_VARIABLE: &str = "WEBSERVER_CONFIG";
+
+/**
+ * Apply overrides from environment variables to a parsed configuration file.
+ *
+ * Values are read as YAML scalars, so "8080" is a number and "true" a boolean. Setting a key of a
+ * section which is missing from the file creates the section.
+ *
+ * # Arguments
+ * * `doc` - The parsed configuration file, which must be a mapping.
+ * * `variables` - The environment variables.
+ * * `warnings` - Collects variables which do not name a configuration key. They are ignored
+ *   rather than being a problem, as the environment is shared with everything else that runs.
+ *
+ * # Returns
+ * The overrides which were applied, as "NAME=value", sorted by name.
+ */
+pub fn apply_env_overrides(doc: &mut Yaml, variables: impl Iterator<Item = (String, String)>, warnings: &mut Vec<String>) -> Vec<String>{
+    let keys: HashMap<String, &str> = OVERRIDABLE_KEYS.iter()
+        .map(|key| (format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase()), *key))
+        .collect();
+    let mut applied = Vec::new();
+    for (name, value) in variables{
+        if !name.starts_with(ENV_PREFIX) || name == CONFIG_PATH_VARIABLE{
+            continue;
+        }
+        let Some(key) = keys.get(&name) else {
+            warnings.push(format!("{name}: not a configuration key which can be set from the environment, ignored"));
+            continue;
+        };
+        let scalar = YamlLoader::load_from_str(&value).ok()
+            .and_then(|docs| docs.into_iter().next())
+            .filter(|scalar| !matches!(scalar, Yaml::Hash(_) | Yaml::Array(_)))
+            .unwrap_or_else(|| Yaml::String(value.clone()));
+        set_path(doc, key, scalar);
+        applied.push(format!("{name}={value}"));
+    }
+    applied.sort();
+    applied
+}
+
+/**
+ * Set a dotted key of a mapping, creating the sections leading to it.
+ */
+fn set_path(doc: &mut Yaml, key: &str, value: Yaml){
+    let mut node = doc;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next(){
+        if !matches!(node, Yaml::Hash(_)){
+            *node = Yaml::Hash(Default::default());
+        }
+        let Yaml::Hash(hash) = node else { unreachable!() };
+        let part = Yaml::String(part.to_string());
+        if parts.peek().is_none(){
+            hash.insert(part, value);
+            return;
+        }
+        if !hash.contains_key(&part){
+            hash.insert(part.clone(), Yaml::Hash(Default::default()));
+        }
+        node = hash.get_mut(&part).unwrap();
+    }
+}
+
+/**
+ * A section of the configuration file, which reads its keys and records every problem found.
+ *
+ * Missing keys are not problems, the caller supplies the default. A key holding a value of the
+ * wrong type is recorded, along with its full path, and treated as missing.
+ */
+#[derive(Clone, Copy)]
+pub struct Section<'a>{
+    yaml: &'a Yaml,
+    path: &'a str
+}
+
+impl<'a> Section<'a>{
+    /**
+     * # Arguments
+     * * `yaml` - The value of the section.
+     * * `path` - The keys leading to the section, such as "tls", or "" for the whole file.
+     */
+    pub fn new(yaml: &'a Yaml, path: &'a str) -> Self{
+        Self{ yaml, path }
+    }
+
+    /**
+     * The full path of a key of the section, as used in problems.
+     */
+    pub fn key(&self, key: &str) -> String{
+        if self.path.is_empty() {key.to_string()} else {format!("{}.{key}", self.path)}
+    }
+
+    /**
+     * The value of a key, or None if it is missing.
+     */
+    pub fn get(&self, key: &str) -> Option<&'a Yaml>{
+        match &self.yaml[key]{
+            Yaml::BadValue | Yaml::Null => None,
+            value => Some(value)
+        }
+    }
+
+    /**
+     * Record a problem with a key of the section.
+     */
+    pub fn problem(&self, key: &str, message: impl Display, problems: &mut Vec<String>){
+        problems.push(format!("{}: {message}", self.key(key)));
+    }
+
+    pub fn string(&self, key: &str, problems: &mut Vec<String>) -> Option<String>{
+        match self.get(key)?{
+            Yaml::String(value) => Some(value.clone()),
+            // a bare number or boolean is fine where text is expected
+            Yaml::Integer(value) => Some(value.to_string()),
+            Yaml::Real(value) => Some(value.clone()),
+            Yaml::Boolean(value) => Some(value.to_string()),
+            _ => {
+                self.problem(key, "expected text", problems);
+                None
+            }
+        }
+    }
+
+    pub fn boolean(&self, key: &str, problems: &mut Vec<String>) -> Option<bool>{
+        match self.get(key)?{
+            Yaml::Boolean(value) => Some(*value),
+            other => {
+                self.problem(key, format!("expected true or false, found {}", describe(other)), problems);
+                None
+            }
+        }
+    }
+
+    /**
+     * Read a whole number, which must fit in T.
+     */
+    pub fn integer<T: TryFrom<i64> + Display + Copy>(&self, key: &str, problems: &mut Vec<String>) -> Option<T>{
+        match self.get(key)?{
+            Yaml::Integer(value) => match T::try_from(*value){
+                Ok(value) => Some(value),
+                Err(_) => {
+                    self.problem(key, format!("{value} is out of range"), problems);
+                    None
+                }
+            },
+            other => {
+                self.problem(key, format!("expected a whole number, found {}", describe(other)), problems);
+                None
+            }
+        }
+    }
+
+    /**
+     * Read a whole number, which must be within a range.
+     */
+    pub fn integer_in<T: TryFrom<i64> + Display + Copy + PartialOrd>(&self, key: &str, range: RangeInclusive<T>, problems: &mut Vec<String>) -> Option<T>{
+        let value = self.integer::<T>(key, problems)?;
+        if !range.contains(&value){
+            self.problem(key, format!("{value} is out of range, expected {} to {}", range.start(), range.end()), problems);
+            return None;
+        }
+        Some(value)
+    }
+
+    /**
+     * Read a port number to listen on, which may not be 0.
+     */
+    pub fn port(&self, key: &str, problems: &mut Vec<String>) -> Option<u16>{
+        match self.integer::<u16>(key, problems)?{
+            0 => {
+                self.problem(key, "the port may not be 0", problems);
+                None
+            },
+            port => Some(port)
+        }
+    }
+
+    /**
+     * Read a nested section. A missing section reads as empty, so all its keys take their defaults.
+     */
+    pub fn section(&self, key: &str, path: &'a str, problems: &mut Vec<String>) -> Section<'a>{
+        match self.get(key){
+            None | Some(Yaml::Hash(_)) => Section::new(&self.yaml[key], path),
+            Some(other) => {
+                self.problem(key, format!("expected a section of keys, found {}", describe(other)), problems);
+                Section::new(&Yaml::BadValue, path)
+            }
+        }
+    }
+
+    /**
+     * Read a list. A missing list reads as empty.
+     */
+    pub fn list(&self, key: &str, problems: &mut Vec<String>) -> &'a [Yaml]{
+        match self.get(key){
+            None => &[],
+            Some(Yaml::Array(items)) => items,
+            Some(other) => {
+                self.problem(key, format!("expected a list, found {}", describe(other)), problems);
+                &[]
+            }
+        }
+    }
+
+    /**
+     * Read every key and value of a mapping as text.
+     */
+    pub fn pairs(&self, key: &str, problems: &mut Vec<String>) -> Vec<(String, String)>{
+        match self.get(key){
+            None => Vec::new(),
+            Some(Yaml::Hash(hash)) => hash.iter()
+                .filter_map(|(name, value)| match (name.as_str(), value.as_str()){
+                    (Some(name), Some(value)) => Some((name.to_string(), value.to_string())),
+                    _ => {
+                        self.problem(key, format!("expected text keys and values, found {}", describe(value)), problems);
+                        None
+                    }
+                })
+                .collect(),
+            Some(other) => {
+                self.problem(key, format!("expected a section of keys, found {}", describe(other)), problems);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/**
+ * Describe a YAML value for a problem.
+ */
+fn describe(value: &Yaml) -> String{
+    match value{
+        Yaml::String(value) => format!("\"{value}\""),
+        Yaml::Integer(value) => value.to_string(),
+        Yaml::Real(value) => value.clone(),
+        Yaml::Boolean(value) => value.to_string(),
+        Yaml::Array(_) => "a list".to_string(),
+        Yaml::Hash(_) => "a section of keys".to_string(),
+        _ => "nothing".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)>{
+        pairs.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn parse(contents: &str) -> Yaml{
+        YamlLoader::load_from_str(contents).unwrap().into_iter().next().unwrap_or(Yaml::Null)
+    }
+
+    #[test]
+    fn overrides_are_read_as_yaml_scalars(){
+        let mut doc = parse("http_port: 8080\nautoindex: false\n");
+        let mut warnings = Vec::new();
+        let applied = apply_env_overrides(&mut doc, variables(&[
+            ("WEBSERVER_HTTP_PORT", "9000"),
+            ("WEBSERVER_AUTOINDEX", "true"),
+            ("WEBSERVER_DOCUMENT_ROOT", "/srv/www"),
+            ("WEBSERVER_LOG_FILTER", "[not, a, list]")
+        ]), &mut warnings);
+        assert_eq!(doc["http_port"], Yaml::Integer(9000));
+        assert_eq!(doc["autoindex"], Yaml::Boolean(true));
+        assert_eq!(doc["document_root"], Yaml::String("/srv/www".to_string()));
+        // only scalars can be set, so anything else is kept as text
+        assert_eq!(doc["log_filter"], Yaml::String("[not, a, list]".to_string()));
+        assert_eq!(applied, [
+            "WEBSERVER_AUTOINDEX=true",
+            "WEBSERVER_DOCUMENT_ROOT=/srv/www",
+            "WEBSERVER_HTTP_PORT=9000",
+            "WEBSERVER_LOG_FILTER=[not, a, list]"
+        ]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn overrides_create_missing_sections(){
+        let mut doc = parse("http_port: 8080\n");
+        let mut warnings = Vec::new();
+        apply_env_overrides(&mut doc, variables(&[
+            ("WEBSERVER_CONNECTIONS_IDLE_TIMEOUT", "5"),
+            ("WEBSERVER_FTP_PASSIVE_MIN_PORT", "50000")
+        ]), &mut warnings);
+        assert_eq!(doc["connections"]["idle_timeout"], Yaml::Integer(5));
+        assert_eq!(doc["ftp_passive"]["min_port"], Yaml::Integer(50000));
+        assert_eq!(doc["http_port"], Yaml::Integer(8080));
+
+        let mut empty = Yaml::Null;
+        apply_env_overrides(&mut empty, variables(&[("WEBSERVER_TLS_PORT", "8443")]), &mut warnings);
+        assert_eq!(empty["tls"]["port"], Yaml::Integer(8443));
+    }
+
+    #[test]
+    fn unknown_variables_are_warnings(){
+        let mut doc = parse("http_port: 8080\n");
+        let mut warnings = Vec::new();
+        let applied = apply_env_overrides(&mut doc, variables(&[
+            ("WEBSERVER_HTTP_PROT", "9000"),
+            ("WEBSERVER_TLS_CERTIFICATES", "x"),
+            ("WEBSERVER_CONFIG", "other.yaml"),
+            ("HTTP_PORT", "9000"),
+            ("PATH", "/usr/bin")
+        ]), &mut warnings);
+        assert!(applied.is_empty());
+        assert_eq!(warnings, [
+            "WEBSERVER_HTTP_PROT: not a configuration key which can be set from the environment, ignored",
+            "WEBSERVER_TLS_CERTIFICATES: not a configuration key which can be set from the environment, ignored"
+        ]);
+        assert_eq!(doc["http_port"], Yaml::Integer(8080));
+    }
+
+    #[test]
+    fn every_key_has_a_variable(){
+        let mut doc = Yaml::Null;
+        let mut warnings = Vec::new();
+        let names: Vec<String> = OVERRIDABLE_KEYS.iter()
+            .map(|key| format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase()))
+            .collect();
+        let applied = apply_env_overrides(&mut doc, names.iter().map(|name| (name.clone(), "1".to_string())), &mut warnings);
+        assert_eq!(applied.len(), OVERRIDABLE_KEYS.len());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn problems_name_the_full_key(){
+        let doc = parse("tls:\n  port: 0\n  reload_interval: soon\nhttp2:\n  enabled: 1\n  max_concurrent_streams: 1.5\n");
+        let mut problems = Vec::new();
+        let root = Section::new(&doc, "");
+        let tls = root.section("tls", "tls", &mut problems);
+        let http2 = root.section("http2", "http2", &mut problems);
+        assert_eq!(tls.port("port", &mut problems), None);
+        assert_eq!(tls.integer::<u64>("reload_interval", &mut problems), None);
+        assert_eq!(http2.boolean("enabled", &mut problems), None);
+        assert_eq!(http2.integer::<u32>("max_concurrent_streams", &mut problems), None);
+        assert_eq!(root.integer::<u16>("missing", &mut problems), None);
+        assert_eq!(problems.len(), 4);
+        assert_eq!(problems[0], "tls.port: the port may not be 0");
+        assert!(problems[1].starts_with("tls.reload_interval: expected a whole number"));
+        assert!(problems[2].starts_with("http2.enabled: expected true or false"));
+        assert!(problems[3].starts_with("http2.max_concurrent_streams: expected a whole number"));
+    }
+}
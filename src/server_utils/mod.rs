@@ -1,14 +1,28 @@
-use std::{fs::File, io::Read};
+use std::{collections::HashMap, fmt, net::{IpAddr, Ipv4Addr, Ipv6Addr}, path::{Path, PathBuf}, time::SystemTime};
 
-use yaml_rust::YamlLoader;
+use hyper::header::{HeaderName, HeaderValue};
+use yaml_rust::{Yaml, YamlLoader};
 
+use config_reader::Section;
+
+pub mod paths;
+mod config_reader;
+
+pub use config_reader::CONFIG_PATH_VARIABLE;
+
+#[allow(clippy::upper_case_acronyms)]
 pub enum FileOpenStatus {
     DNE,
     ERROR,
+    FORBIDDEN,
     SUCCESS
 }
 
 
+/**
+ * A server which the serve command can start.
+ */
+#[allow(clippy::upper_case_acronyms)]
 pub enum ServerMode {
     HTTP,
     FTP
@@ -16,34 +30,778 @@ pub enum ServerMode {
 
 impl PartialEq for ServerMode{
     fn eq(&self, other: &Self) -> bool{
-        match (self, other){
-            (ServerMode::HTTP, ServerMode::HTTP) => true,
-            (ServerMode::FTP, ServerMode::FTP) => true,
-            _ => false
+        matches!((self, other), (ServerMode::HTTP, ServerMode::HTTP) | (ServerMode::FTP, ServerMode::FTP))
+    }
+}
+/**
+ * A single entry of a directory listing.
+ */
+pub struct DirectoryEntry{
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>
+}
+
+/**
+ * Read the entries of a directory, along with the metadata needed to list them.
+ * 
+ * Shared by FTP LIST and HTTP directory listings.
+ * 
+ * # Arguments
+ * * `path` - The directory to read.
+ */
+pub fn read_directory<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<DirectoryEntry>>{
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(path)?{
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        entries.push(DirectoryEntry{
+            name: entry.file_name().into_string().unwrap_or_default(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok()
+        });
+    }
+    Ok(entries)
+}
+
+/**
+ * A middleware to run around every HTTP request, as listed in the configuration.
+ */
+pub enum MiddlewareConfig{
+    // names and values of headers added to every response
+    Headers(Vec<(String, String)>),
+    // usernames and sha256 password hashes
+    BasicAuth{ realm: String, users: HashMap<String, String> }
+}
+
+impl MiddlewareConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> Option<MiddlewareConfig>{
+        match yaml.string("type", problems).as_deref(){
+            Some("headers") => {
+                let headers = yaml.pairs("headers", problems);
+                for (name, value) in &headers{
+                    if HeaderName::from_bytes(name.as_bytes()).is_err(){
+                        yaml.problem("headers", format!("\"{name}\" is not a valid header name"), problems);
+                    }else if HeaderValue::from_str(value).is_err(){
+                        yaml.problem(&format!("headers.{name}"), format!("\"{value}\" is not a valid header value"), problems);
+                    }
+                }
+                Some(MiddlewareConfig::Headers(headers))
+            },
+            Some("basic_auth") => {
+                let realm = yaml.string("realm", problems).unwrap_or("Restricted".to_string());
+                // the realm is sent quoted in WWW-Authenticate
+                if realm.contains('"') || HeaderValue::from_str(&realm).is_err(){
+                    yaml.problem("realm", format!("\"{realm}\" cannot be sent in a header"), problems);
+                }
+                let users: HashMap<String, String> = yaml.pairs("users", problems).into_iter().collect();
+                for (username, hash) in &users{
+                    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()){
+                        yaml.problem(&format!("users.{username}"), "expected the sha256 hash of the password, as written by hash-password", problems);
+                    }
+                }
+                Some(MiddlewareConfig::BasicAuth{ realm, users })
+            },
+            Some(other) => {
+                yaml.problem("type", format!("unknown middleware type \"{other}\", expected headers or basic_auth"), problems);
+                None
+            },
+            None => {
+                yaml.problem("type", "is missing", problems);
+                None
+            }
+        }
+    }
+}
+
+/**
+ * A certificate, its private key, and the server names it is presented for.
+ */
+pub struct CertificateConfig{
+    pub cert: String,
+    pub key: String,
+    // an empty list means the certificate is only used as the default
+    pub server_names: Vec<String>
+}
+
+/**
+ * Settings of the HTTPS listener.
+ */
+pub struct TlsConfig{
+    pub port: u16,
+    // seconds between checks of the certificate files for changes
+    pub reload_interval: u64,
+    // the first certificate is used when no server name matches
+    pub certificates: Vec<CertificateConfig>
+}
+
+impl TlsConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> TlsConfig{
+        let port = yaml.port("port", problems).unwrap_or(8443);
+        let reload_interval = yaml.integer("reload_interval", problems).unwrap_or(10);
+        let entries = yaml.list("certificates", problems);
+        if entries.is_empty(){
+            yaml.problem("certificates", "needs at least one certificate", problems);
+        }
+        let certificates = entries.iter()
+            .enumerate()
+            .filter_map(|(index, certificate)| {
+                let path = yaml.key(&format!("certificates[{index}]"));
+                let certificate = Section::new(certificate, &path);
+                let cert = certificate.string("cert", problems);
+                let key = certificate.string("key", problems);
+                for (name, value) in [("cert", &cert), ("key", &key)]{
+                    if value.is_none(){
+                        certificate.problem(name, "is missing", problems);
+                    }
+                }
+                let server_names = certificate.list("server_names", problems)
+                    .iter()
+                    .filter_map(|name| Some(name.as_str()?.to_lowercase()))
+                    .collect();
+                Some(CertificateConfig{ cert: cert?, key: key?, server_names })
+            })
+            .collect();
+        TlsConfig{ port, reload_interval, certificates }
+    }
+}
+
+// the largest HTTP/2 flow control window, 2^31-1
+const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+// HTTP/2 frames may be made no smaller than the default of 2^14, and no larger than 2^24-1
+const MIN_FRAME_SIZE: u32 = 1 << 14;
+const MAX_FRAME_SIZE: u32 = (1 << 24) - 1;
+
+/**
+ * Settings of HTTP/2 connections. Unset values use the defaults of hyper.
+ */
+pub struct Http2Config{
+    // when false, every connection is served as HTTP/1.1
+    pub enabled: bool,
+    pub max_concurrent_streams: Option<u32>,
+    // flow control windows, in bytes
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    // grow the windows to match the measured bandwidth, ignoring the sizes above
+    pub adaptive_window: bool,
+    pub max_frame_size: Option<u32>
+}
+
+impl Http2Config{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> Http2Config{
+        // the limits of RFC 9113, outside of which h2 refuses the settings by panicking
+        let window_sizes = 0..=MAX_WINDOW_SIZE;
+        Http2Config{
+            enabled: yaml.boolean("enabled", problems).unwrap_or(true),
+            max_concurrent_streams: yaml.integer("max_concurrent_streams", problems),
+            initial_stream_window_size: yaml.integer_in("initial_stream_window_size", window_sizes.clone(), problems),
+            initial_connection_window_size: yaml.integer_in("initial_connection_window_size", window_sizes, problems),
+            adaptive_window: yaml.boolean("adaptive_window", problems).unwrap_or(false),
+            max_frame_size: yaml.integer_in("max_frame_size", MIN_FRAME_SIZE..=MAX_FRAME_SIZE, problems)
+        }
+    }
+}
+
+/**
+ * Limits applied to each HTTP connection. Timeouts are in seconds.
+ */
+pub struct ConnectionConfig{
+    // time allowed to receive the headers of a request, from its first byte
+    pub header_read_timeout: u64,
+    // time a connection is kept open with no request in progress
+    pub idle_timeout: u64,
+    // time allowed to receive the body of a request, from when its headers arrive
+    pub request_body_timeout: u64,
+    // time a write may wait for the client to read
+    pub write_timeout: u64,
+    // requests served on a connection before it is closed, unlimited if unset
+    pub max_requests: Option<u64>
+}
+
+impl ConnectionConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> ConnectionConfig{
+        ConnectionConfig{
+            header_read_timeout: yaml.integer("header_read_timeout", problems).unwrap_or(10),
+            idle_timeout: yaml.integer("idle_timeout", problems).unwrap_or(60),
+            request_body_timeout: yaml.integer("request_body_timeout", problems).unwrap_or(30),
+            write_timeout: yaml.integer("write_timeout", problems).unwrap_or(30),
+            max_requests: yaml.integer("max_requests", problems)
+        }
+    }
+}
+
+/**
+ * What happens to a connection which would exceed a connection limit.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum LimitPolicy{
+    // close it immediately
+    Refuse,
+    // hold it until a connection closes, up to max_queued connections for up to queue_timeout
+    Queue
+}
+
+/**
+ * Caps on the connections each listener serves at once. Unset caps are unlimited.
+ */
+#[derive(Clone)]
+pub struct ConnectionLimitsConfig{
+    pub max_connections: Option<u32>,
+    pub max_connections_per_ip: Option<u32>,
+    pub policy: LimitPolicy,
+    pub max_queued: usize,
+    // seconds a connection may wait in the queue before it is dropped
+    pub queue_timeout: u64
+}
+
+impl ConnectionLimitsConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> ConnectionLimitsConfig{
+        let policy = match yaml.string("policy", problems).as_deref(){
+            None | Some("refuse") => LimitPolicy::Refuse,
+            Some("queue") => LimitPolicy::Queue,
+            Some(other) => {
+                yaml.problem("policy", format!("unknown policy \"{other}\", expected refuse or queue"), problems);
+                LimitPolicy::Refuse
+            }
+        };
+        ConnectionLimitsConfig{
+            max_connections: yaml.integer("max_connections", problems),
+            max_connections_per_ip: yaml.integer("max_connections_per_ip", problems),
+            policy,
+            max_queued: yaml.integer("max_queued", problems).unwrap_or(128),
+            queue_timeout: yaml.integer("queue_timeout", problems).unwrap_or(30)
         }
     }
 }
+
+/**
+ * The line format of the access log.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat{
+    // the Common Log Format
+    Common,
+    // the Combined Log Format
+    Combined,
+    // the Combined Log Format, followed by the time taken in milliseconds
+    Extended,
+    // one JSON object per line
+    Json
+}
+
+/**
+ * Settings of the access log, shared by the HTTP and FTP servers.
+ */
+pub struct AccessLogConfig{
+    pub format: LogFormat,
+    // standard output if unset
+    pub path: Option<String>,
+    // rotate once the file would grow past this many bytes
+    pub max_size: Option<u64>,
+    // rotate once the file has been written to for this many seconds
+    pub rotate_interval: Option<u64>,
+    // rotated files kept, as path.1 (newest) to path.N
+    pub keep: usize
+}
+
+impl AccessLogConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> AccessLogConfig{
+        let format = match yaml.string("format", problems).as_deref(){
+            Some("common") => LogFormat::Common,
+            None | Some("combined") => LogFormat::Combined,
+            Some("extended") => LogFormat::Extended,
+            Some("json") => LogFormat::Json,
+            Some(other) => {
+                yaml.problem("format", format!("unknown format \"{other}\", expected common, combined, extended or json"), problems);
+                LogFormat::Combined
+            }
+        };
+        AccessLogConfig{
+            format,
+            path: yaml.string("path", problems),
+            max_size: yaml.integer("max_size", problems),
+            rotate_interval: yaml.integer("rotate_interval", problems),
+            keep: yaml.integer("keep", problems).unwrap_or(5)
+        }
+    }
+}
+
+/**
+ * Which kinds of address the listeners accept connections on.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum AddressFamily{
+    Ipv4,
+    Ipv6,
+    // an IPv6 socket which also accepts IPv4 connections
+    Dual
+}
+
+/**
+ * The address every listener binds to, each on its own port.
+ */
+pub struct ListenConfig{
+    pub family: AddressFamily,
+    // the loopback address of the family if unset, or every address for dual-stack
+    pub address: Option<IpAddr>
+}
+
+impl ListenConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> ListenConfig{
+        let family = match yaml.string("family", problems).as_deref(){
+            None | Some("ipv4") => AddressFamily::Ipv4,
+            Some("ipv6") => AddressFamily::Ipv6,
+            Some("dual") => AddressFamily::Dual,
+            Some(other) => {
+                yaml.problem("family", format!("unknown family \"{other}\", expected ipv4, ipv6 or dual"), problems);
+                AddressFamily::Ipv4
+            }
+        };
+        let address = yaml.string("address", problems).and_then(|address| match address.parse::<IpAddr>(){
+            Ok(address) => Some(address),
+            Err(_) => {
+                yaml.problem("address", format!("\"{address}\" is not an IP address"), problems);
+                None
+            }
+        });
+        let listen = ListenConfig{ family, address };
+        if let Err(message) = listen.check(){
+            yaml.problem("address", message, problems);
+        }
+        listen
+    }
+
+    /**
+     * The address the listeners bind to.
+     */
+    pub fn address(&self) -> IpAddr{
+        self.address.unwrap_or(match self.family{
+            AddressFamily::Ipv4 => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            AddressFamily::Ipv6 => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            AddressFamily::Dual => IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        })
+    }
+
+    /**
+     * Bind to an address given in place of the configured one, with the family taken from it.
+     *
+     * An IPv6 address keeps a configured dual-stack family, as that is also bound to an IPv6
+     * address.
+     */
+    pub fn bind_to(&mut self, address: IpAddr){
+        self.family = match (address, self.family){
+            (IpAddr::V4(_), _) => AddressFamily::Ipv4,
+            (IpAddr::V6(_), AddressFamily::Dual) => AddressFamily::Dual,
+            (IpAddr::V6(_), _) => AddressFamily::Ipv6
+        };
+        self.address = Some(address);
+    }
+
+    /**
+     * Check that the address belongs to the family: IPv4 for ipv4, and IPv6 for ipv6 and dual.
+     */
+    pub fn check(&self) -> Result<(), String>{
+        match (self.family, self.address()){
+            (AddressFamily::Ipv4, IpAddr::V4(_)) | (AddressFamily::Ipv6 | AddressFamily::Dual, IpAddr::V6(_)) => Ok(()),
+            (AddressFamily::Ipv4, address) => Err(format!("{address} is not an IPv4 address, which the ipv4 family needs")),
+            (_, address) => Err(format!("{address} is not an IPv6 address, which the ipv6 and dual families need"))
+        }
+    }
+}
+
+/**
+ * Settings of FTP passive mode, where the client opens the data connection.
+ */
+pub struct FtpPassiveConfig{
+    // the ports listened on for data connections, inclusive
+    pub min_port: u16,
+    pub max_port: u16,
+    // the address sent in PASV replies, for servers behind NAT; the local address if unset
+    pub external_address: Option<Ipv4Addr>,
+    // seconds to wait for the client to connect
+    pub accept_timeout: u64
+}
+
+impl FtpPassiveConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> FtpPassiveConfig{
+        let min_port = yaml.port("min_port", problems).unwrap_or(50000);
+        let max_port = yaml.port("max_port", problems).unwrap_or(50100);
+        if min_port > max_port{
+            yaml.problem("max_port", format!("{max_port} is below min_port {min_port}"), problems);
+        }
+        let external_address = yaml.string("external_address", problems).and_then(|address| match address.parse(){
+            Ok(address) => Some(address),
+            Err(_) => {
+                yaml.problem("external_address", format!("\"{address}\" is not an IPv4 address"), problems);
+                None
+            }
+        });
+        FtpPassiveConfig{
+            min_port,
+            max_port,
+            external_address,
+            accept_timeout: yaml.integer("accept_timeout", problems).unwrap_or(10)
+        }
+    }
+}
+
+/**
+ * The admin listener, which serves health checks, status and metrics of the other listeners.
+ */
+pub struct AdminConfig{
+    pub port: u16
+}
+impl AdminConfig{
+    fn from_yaml(yaml: Section, problems: &mut Vec<String>) -> AdminConfig{
+        AdminConfig{
+            port: yaml.port("port", problems).unwrap_or(9090)
+        }
+    }
+}
+
 pub struct Config{
+    pub listen: ListenConfig,
     pub http_port: u16,
-    pub ftp_control_port: u16
+    pub ftp_control_port: u16,
+    pub document_root: String,
+    pub autoindex: bool,
+    pub mime_types_file: Option<String>,
+    pub middleware: Vec<MiddlewareConfig>,
+    pub tls: Option<TlsConfig>,
+    pub http2: Http2Config,
+    pub connections: ConnectionConfig,
+    pub connection_limits: ConnectionLimitsConfig,
+    pub access_log: Option<AccessLogConfig>,
+    // which diagnostics are logged, in the syntax of RUST_LOG, such as "info,WebServer::server_core::ftp=debug"
+    pub log_filter: Option<String>,
+    pub admin: Option<AdminConfig>,
+    // holds username.passwd for each FTP user, as written by the hash-password command
+    pub ftp_users_directory: String,
+    // holds a directory for each FTP user, which is all they can see
+    pub ftp_homes_directory: String,
+    pub ftp_passive: FtpPassiveConfig,
+    // sha256 of the configuration file, to tell which configuration a server is running
+    pub hash: String,
+    // what was ignored while loading, such as unknown WEBSERVER_* variables, logged once logging is set up
+    pub warnings: Vec<String>
 }
 
+/**
+ * Why the configuration could not be loaded.
+ */
+#[derive(Debug)]
+pub enum ConfigError{
+    // the file could not be read
+    Read{ path: PathBuf, error: std::io::Error },
+    // the file is not valid YAML
+    Parse{ path: PathBuf, error: yaml_rust::ScanError },
+    // every problem found with the values, one per line
+    Invalid{ path: PathBuf, problems: Vec<String> }
+}
+
+impl fmt::Display for ConfigError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            ConfigError::Read{ path, error } => write!(f, "Could not read config file {}: {error}", path.display()),
+            ConfigError::Parse{ path, error } => write!(f, "Could not parse config file {}: {error}", path.display()),
+            ConfigError::Invalid{ path, problems } => {
+                write!(f, "Invalid config file {}:", path.display())?;
+                for problem in problems{
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError{}
+
 impl Config{
-    pub fn new() -> Config{
-        // The file is in the crate root, because the src/ directory
-        let mut file = File::open("/home/andrewheschl/Documents/WebServer/config.yaml").expect("Could not open config file");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).expect("Could not read config file");
-
-        let docs = YamlLoader::load_from_str(&contents).unwrap();
-        let doc = &docs[0];
-        let http_port: u16 = doc["http_port"].as_i64()
-            .expect("Could not find http_port") as u16;
-        let ftp_control_port : u16 = doc["ftp_control_port"] .as_i64().expect("Cannot find ftp_control_port") as u16;
+    /**
+     * The path of the configuration file, when none is given on the command line: WEBSERVER_CONFIG
+     * if it is set, and config.yaml in the working directory otherwise.
+     */
+    pub fn default_path() -> PathBuf{
+        std::env::var_os(CONFIG_PATH_VARIABLE)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("config.yaml"))
+    }
+
+    /**
+     * Load the configuration from a file, with overrides from WEBSERVER_* environment variables.
+     *
+     * Every key has a default, so an empty file is a valid configuration. Rather than stopping at
+     * the first problem, every value is checked and all the problems are returned together.
+     *
+     * # Arguments
+     * * `path` - The path of the configuration file.
+     */
+    pub fn load(path: &Path) -> Result<Config, ConfigError>{
+        Config::load_from(path, std::env::vars())
+    }
+
+    /**
+     * Load the configuration from a file, with overrides from the given environment variables.
+     */
+    fn load_from(path: &Path, variables: impl Iterator<Item = (String, String)>) -> Result<Config, ConfigError>{
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::Read{ path: path.to_path_buf(), error })?;
+        let mut doc = YamlLoader::load_from_str(&contents)
+            .map_err(|error| ConfigError::Parse{ path: path.to_path_buf(), error })?
+            .into_iter()
+            .next()
+            .unwrap_or(Yaml::Null);
+
+        let mut problems = Vec::new();
+        if !matches!(doc, Yaml::Hash(_) | Yaml::Null){
+            problems.push("the file must be a section of keys".to_string());
+        }
+        let mut warnings = Vec::new();
+        let overrides = config_reader::apply_env_overrides(&mut doc, variables, &mut warnings);
+        let config = Config::from_yaml(Section::new(&doc, ""), &contents, &overrides, &mut problems);
+        if problems.is_empty(){
+            Ok(Config{ warnings, ..config })
+        }else{
+            Err(ConfigError::Invalid{ path: path.to_path_buf(), problems })
+        }
+    }
+
+    fn from_yaml(doc: Section, contents: &str, overrides: &[String], problems: &mut Vec<String>) -> Config{
+        // a missing section listens on 127.0.0.1
+        let listen = ListenConfig::from_yaml(doc.section("listen", "listen", problems), problems);
+        let http_port = doc.port("http_port", problems).unwrap_or(8080);
+        let ftp_control_port = doc.port("ftp_control_port", problems).unwrap_or(2121);
+        let document_root = doc.string("document_root", problems).unwrap_or("./public".to_string());
+        if !Path::new(&document_root).is_dir(){
+            doc.problem("document_root", format!("{document_root} is not a directory"), problems);
+        }
+        // directory listings are opt-in
+        let autoindex = doc.boolean("autoindex", problems).unwrap_or(false);
+        // extra extensions, in the format of /etc/mime.types
+        let mime_types_file = doc.string("mime_types_file", problems);
+        // run around every HTTP request, outermost first
+        let middleware = doc.list("middleware", problems)
+            .iter()
+            .enumerate()
+            .filter_map(|(index, middleware)| {
+                let path = format!("middleware[{index}]");
+                MiddlewareConfig::from_yaml(Section::new(middleware, &path), problems)
+            })
+            .collect();
+        // HTTPS is only served when the section is present
+        let tls = doc.get("tls").map(|_| TlsConfig::from_yaml(doc.section("tls", "tls", problems), problems));
+        // a missing section enables HTTP/2 with the defaults
+        let http2 = Http2Config::from_yaml(doc.section("http2", "http2", problems), problems);
+        // a missing section uses the default limits
+        let connections = ConnectionConfig::from_yaml(doc.section("connections", "connections", problems), problems);
+        // applied to each listener, HTTP and FTP alike
+        let connection_limits = ConnectionLimitsConfig::from_yaml(doc.section("connection_limits", "connection_limits", problems), problems);
+        // requests are only logged when the section is present
+        let access_log = doc.get("access_log")
+            .map(|_| AccessLogConfig::from_yaml(doc.section("access_log", "access_log", problems), problems));
+        // RUST_LOG takes precedence when it is set
+        let log_filter = doc.string("log_filter", problems);
+        let ftp_users_directory = doc.string("ftp_users_directory", problems).unwrap_or("./ftp_users".to_string());
+        // each home is created the first time its user logs in, anonymous users see the document root
+        let ftp_homes_directory = doc.string("ftp_homes_directory", problems).unwrap_or("./ftp_homes".to_string());
+        // a missing section listens on the default port range
+        let ftp_passive = FtpPassiveConfig::from_yaml(doc.section("ftp_passive", "ftp_passive", problems), problems);
+        // health checks and metrics are only served when the section is present
+        let admin = doc.get("admin").map(|_| AdminConfig::from_yaml(doc.section("admin", "admin", problems), problems));
+
+        // every listener needs a port of its own
+        let mut ports = vec![("http_port", http_port), ("ftp_control_port", ftp_control_port)];
+        ports.extend(tls.as_ref().map(|tls| ("tls.port", tls.port)));
+        ports.extend(admin.as_ref().map(|admin| ("admin.port", admin.port)));
+        for (index, (name, port)) in ports.iter().enumerate(){
+            if let Some((other, _)) = ports[..index].iter().find(|(_, other_port)| other_port == port){
+                problems.push(format!("{name}: port {port} is already used by {other}"));
+            }
+        }
+
+        // overrides change the configuration, so they are part of what is hashed
+        let mut hashed = contents.to_string();
+        for assignment in overrides{
+            hashed.push('\n');
+            hashed.push_str(assignment);
+        }
 
         Config{
+            listen,
             http_port,
-            ftp_control_port
+            ftp_control_port,
+            document_root,
+            autoindex,
+            mime_types_file,
+            middleware,
+            tls,
+            http2,
+            connections,
+            connection_limits,
+            access_log,
+            log_filter,
+            admin,
+            ftp_users_directory,
+            ftp_homes_directory,
+            ftp_passive,
+            hash: sha256::digest(hashed),
+            warnings: Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /**
+     * Read a configuration from YAML, without environment overrides.
+     *
+     * # Returns
+     * The configuration, or every problem found with it.
+     */
+    fn load(contents: &str) -> Result<Config, Vec<String>>{
+        let doc = YamlLoader::load_from_str(contents).unwrap().into_iter().next().unwrap_or(Yaml::Null);
+        let mut problems = Vec::new();
+        let config = Config::from_yaml(Section::new(&doc, ""), contents, &[], &mut problems);
+        if problems.is_empty() {Ok(config)} else {Err(problems)}
+    }
+
+    #[test]
+    fn http2_settings_within_limits(){
+        let config = load("document_root: src\nhttp2:\n  max_frame_size: 16384\n  initial_stream_window_size: 2147483647\n  initial_connection_window_size: 0\n").ok().unwrap();
+        assert_eq!(config.http2.max_frame_size, Some(16384));
+        assert_eq!(config.http2.initial_stream_window_size, Some(2147483647));
+        assert_eq!(config.http2.initial_connection_window_size, Some(0));
+        let config = load("document_root: src\nhttp2:\n  max_frame_size: 16777215\n").ok().unwrap();
+        assert_eq!(config.http2.max_frame_size, Some(16777215));
+    }
+
+    #[test]
+    fn http2_settings_out_of_range(){
+        let problems = load("document_root: src\nhttp2:\n  max_frame_size: 100\n  initial_stream_window_size: 2147483648\n  initial_connection_window_size: -1\n").err().unwrap();
+        assert_eq!(problems, [
+            "http2.initial_stream_window_size: 2147483648 is out of range, expected 0 to 2147483647",
+            "http2.initial_connection_window_size: -1 is out of range",
+            "http2.max_frame_size: 100 is out of range, expected 16384 to 16777215"
+        ]);
+        let problems = load("document_root: src\nhttp2:\n  max_frame_size: 16777216\n").err().unwrap();
+        assert_eq!(problems, ["http2.max_frame_size: 16777216 is out of range, expected 16384 to 16777215"]);
+    }
+
+    /**
+     * Write a configuration file to a new directory, which is removed when dropped.
+     */
+    fn config_file(contents: &str) -> (PathBuf, tempfile::TempDir){
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.yaml");
+        std::fs::write(&path, contents).unwrap();
+        (path, directory)
+    }
+
+    fn variables(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)>{
+        pairs.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn empty_file_uses_the_defaults(){
+        let (path, _directory) = config_file("");
+        let config = Config::load_from(&path, variables(&[])).ok().unwrap();
+        assert_eq!(config.http_port, 8080);
+        assert_eq!(config.ftp_control_port, 2121);
+        assert_eq!(config.document_root, "./public");
+        assert!(!config.autoindex);
+        assert!(config.http2.enabled);
+        assert_eq!(config.connections.idle_timeout, 60);
+        assert_eq!(config.connection_limits.queue_timeout, 30);
+        assert!(config.tls.is_none());
+        assert!(config.access_log.is_none());
+        assert!(config.admin.is_none());
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn invalid_file_lists_every_problem(){
+        let (path, _directory) = config_file(
+            "http_port: 0\ndocument_root: /does/not/exist\nlisten:\n  family: ipx\nconnection_limits:\n  policy: drop\naccess_log:\n  format: fancy\n"
+        );
+        let problems = match Config::load_from(&path, variables(&[])){
+            Err(ConfigError::Invalid{ problems, .. }) => problems,
+            _ => panic!("expected the configuration to be invalid")
+        };
+        assert_eq!(problems.len(), 5, "{problems:?}");
+        for key in ["http_port:", "document_root:", "listen.family:", "connection_limits.policy:", "access_log.format:"]{
+            assert!(problems.iter().any(|problem| problem.starts_with(key)), "no problem with {key} in {problems:?}");
         }
     }
+
+    #[test]
+    fn unreadable_and_unparsable_files(){
+        let (path, directory) = config_file("http_port: [8080\n");
+        assert!(matches!(Config::load_from(&path, variables(&[])), Err(ConfigError::Parse{ .. })));
+        let missing = directory.path().join("missing.yaml");
+        assert!(matches!(Config::load_from(&missing, variables(&[])), Err(ConfigError::Read{ .. })));
+        let (path, _directory) = config_file("- a list\n");
+        assert!(matches!(Config::load_from(&path, variables(&[])), Err(ConfigError::Invalid{ .. })));
+    }
+
+    #[test]
+    fn environment_overrides_the_file(){
+        let (path, _directory) = config_file("http_port: 8080\nconnections:\n  idle_timeout: 10\n");
+        let config = Config::load_from(&path, variables(&[
+            ("WEBSERVER_HTTP_PORT", "9000"),
+            ("WEBSERVER_CONNECTIONS_IDLE_TIMEOUT", "5"),
+            ("WEBSERVER_HTTP2_ENABLED", "false"),
+            ("WEBSERVER_UNKNOWN", "1")
+        ])).ok().unwrap();
+        assert_eq!(config.http_port, 9000);
+        assert_eq!(config.connections.idle_timeout, 5);
+        assert!(!config.http2.enabled);
+        assert_eq!(config.warnings, ["WEBSERVER_UNKNOWN: not a configuration key which can be set from the environment, ignored"]);
+        // the overrides are part of the configuration, so they change its hash
+        let unchanged = Config::load_from(&path, variables(&[])).ok().unwrap();
+        assert_ne!(config.hash, unchanged.hash);
+    }
+
+    #[test]
+    fn invalid_overrides_are_problems(){
+        let (path, _directory) = config_file("");
+        let problems = match Config::load_from(&path, variables(&[("WEBSERVER_HTTP_PORT", "eighty"), ("WEBSERVER_ADMIN_PORT", "70000")])){
+            Err(ConfigError::Invalid{ problems, .. }) => problems,
+            _ => panic!("expected the configuration to be invalid")
+        };
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("http_port: expected a whole number"));
+        assert_eq!(problems[1], "admin.port: 70000 is out of range");
+    }
+
+    #[test]
+    fn middleware_which_cannot_be_sent_is_a_problem(){
+        let problems = load(concat!(
+            "document_root: src\n",
+            "middleware:\n",
+            "  - type: headers\n",
+            "    headers:\n",
+            "      X-Frame-Options: DENY\n",
+            "      Bad Name: x\n",
+            "      X-Bad-Value: \"a\\nb\"\n",
+            "  - type: basic_auth\n",
+            "    realm: 'say \"hi\"'\n",
+            "    users:\n",
+            "      alice: s3cret\n",
+            "      bob: 2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b\n"
+        )).err().unwrap();
+        assert_eq!(problems, [
+            "middleware[0].headers: \"Bad Name\" is not a valid header name",
+            "middleware[0].headers.X-Bad-Value: \"a\nb\" is not a valid header value",
+            "middleware[1].realm: \"say \"hi\"\" cannot be sent in a header",
+            "middleware[1].users.alice: expected the sha256 hash of the password, as written by hash-password"
+        ]);
+    }
 }
@@ -0,0 +1,194 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+
+use crate::server_utils::FileOpenStatus;
+
+/**
+ * Percent-decode a request path.
+ * 
+ * Returns None if the decoded bytes are not valid UTF-8, or contain a NUL byte.
+ * 
+ * # Arguments
+ * * `path` - The raw path, as it appears in the request URI.
+ */
+pub fn percent_decode(path: &str) -> Option<String>{
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    if decoded.contains('\0'){
+        return None;
+    }
+    Some(decoded.into_owned())
+}
+
+/**
+ * Normalize a '/' separated path into its segments.
+ * 
+ * Empty and "." segments are dropped, and ".." removes the previous segment.
+ * Returns None if a ".." segment would climb above the root.
+ * 
+ * # Arguments
+ * * `path` - The path to normalize.
+ */
+pub fn normalize_segments(path: &str) -> Option<Vec<&str>>{
+    let mut segments = Vec::new();
+    for segment in path.split('/'){
+        match segment{
+            "" | "." => {},
+            ".." => {
+                segments.pop()?;
+            },
+            _ => segments.push(segment)
+        }
+    }
+    Some(segments)
+}
+
+/**
+ * Map a request path onto a file below the document root.
+ * 
+ * The path is percent-decoded and normalized before being joined to the root. The result is
+ * canonicalized, so symlinks which point outside of the root are rejected as well.
+ * 
+ * # Arguments
+ * * `root` - The directory which all resolved paths must stay inside of.
+ * * `path` - The raw path, as it appears in the request URI.
+ * 
+ * # Returns
+ * The canonical path of the file, or FORBIDDEN if the path escapes the root, DNE if it does not
+ * exist, and ERROR if the filesystem could not be queried.
+ */
+pub async fn resolve_under_root(root: &str, path: &str) -> Result<PathBuf, FileOpenStatus>{
+    let decoded = percent_decode(path).ok_or(FileOpenStatus::FORBIDDEN)?;
+    let segments = normalize_segments(&decoded).ok_or(FileOpenStatus::FORBIDDEN)?;
+    resolve_segments_under_root(Path::new(root), &segments).await
+}
+
+/**
+ * Map normalized path segments onto a file below a root directory.
+ * 
+ * The result is canonicalized, so symlinks which point outside of the root are rejected.
+ * 
+ * # Arguments
+ * * `root` - The directory which all resolved paths must stay inside of.
+ * * `segments` - The path below the root, as returned by normalize_segments.
+ * 
+ * # Returns
+ * The canonical path of the file, or FORBIDDEN if it is outside of the root, DNE if it does not
+ * exist, and ERROR if the filesystem could not be queried.
+ */
+pub async fn resolve_segments_under_root(root: &Path, segments: &[&str]) -> Result<PathBuf, FileOpenStatus>{
+    let root = tokio::fs::canonicalize(root)
+        .await
+        .map_err(|_| FileOpenStatus::ERROR)?;
+    let joined = segments.iter().fold(root.clone(), |joined, segment| joined.join(segment));
+
+    let resolved = match tokio::fs::canonicalize(&joined).await{
+        Ok(resolved) => resolved,
+        // a path through a regular file, such as /a.txt/, names nothing rather than failing
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => return Err(FileOpenStatus::DNE),
+        Err(_) => return Err(FileOpenStatus::ERROR)
+    };
+    // a symlink inside of the root may still point outside of it
+    if !resolved.starts_with(&root){
+        return Err(FileOpenStatus::FORBIDDEN);
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /**
+     * A document root holding index.html and docs/a.txt, next to a directory outside of it holding
+     * secret.txt, with symlinks from the root to a file inside it and to the directory outside.
+     */
+    fn document_root() -> (String, PathBuf, TempDir){
+        let directory = tempfile::tempdir().unwrap();
+        let base = directory.path().canonicalize().unwrap();
+        let root = base.join("root");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("index.html"), "index").unwrap();
+        std::fs::write(root.join("docs/a.txt"), "a").unwrap();
+        std::fs::create_dir(base.join("outside")).unwrap();
+        std::fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+        symlink(root.join("docs/a.txt"), root.join("alias.txt")).unwrap();
+        symlink(base.join("outside"), root.join("escape")).unwrap();
+        symlink(base.join("outside/secret.txt"), root.join("leak.txt")).unwrap();
+        (root.to_str().unwrap().to_string(), root, directory)
+    }
+
+    #[test]
+    fn normalizes_segments(){
+        assert_eq!(normalize_segments("/a//./b/../c/"), Some(vec!["a", "c"]));
+        assert_eq!(normalize_segments("/"), Some(vec![]));
+        assert_eq!(normalize_segments("/a/../.."), None);
+        assert_eq!(normalize_segments("/.."), None);
+    }
+
+    #[test]
+    fn decodes_paths(){
+        assert_eq!(percent_decode("/a%20b/%2e%2E").as_deref(), Some("/a b/.."));
+        assert_eq!(percent_decode("/index.html%00.txt"), None);
+        assert_eq!(percent_decode("/%ff"), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_files_below_the_root(){
+        let (root_path, root, _directory) = document_root();
+        assert_eq!(resolve_under_root(&root_path, "/index.html").await.ok(), Some(root.join("index.html")));
+        assert_eq!(resolve_under_root(&root_path, "/docs/../docs/./a.txt").await.ok(), Some(root.join("docs/a.txt")));
+        assert_eq!(resolve_under_root(&root_path, "/docs/%61.txt").await.ok(), Some(root.join("docs/a.txt")));
+        assert_eq!(resolve_under_root(&root_path, "/").await.ok(), Some(root.clone()));
+        // a symlink to a file inside the root resolves to that file
+        assert_eq!(resolve_under_root(&root_path, "/alias.txt").await.ok(), Some(root.join("docs/a.txt")));
+    }
+
+    #[tokio::test]
+    async fn parent_segments_above_the_root_are_forbidden(){
+        let (root_path, _root, _directory) = document_root();
+        assert!(matches!(resolve_under_root(&root_path, "/../outside/secret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/docs/../../outside/secret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/%2e%2e/outside/secret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/docs/%2E%2E/%2e%2e/outside").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/..%2foutside%2fsecret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+    }
+
+    #[tokio::test]
+    async fn undecodable_paths_are_forbidden(){
+        let (root_path, _root, _directory) = document_root();
+        assert!(matches!(resolve_under_root(&root_path, "/index.html%00").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/index.html%00.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/%c0%ae%c0%ae/secret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+    }
+
+    #[tokio::test]
+    async fn symlinks_out_of_the_root_are_forbidden(){
+        let (root_path, _root, _directory) = document_root();
+        assert!(matches!(resolve_under_root(&root_path, "/leak.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/escape").await, Err(FileOpenStatus::FORBIDDEN)));
+        assert!(matches!(resolve_under_root(&root_path, "/escape/secret.txt").await, Err(FileOpenStatus::FORBIDDEN)));
+    }
+
+    #[tokio::test]
+    async fn missing_files_do_not_exist(){
+        let (root_path, _root, _directory) = document_root();
+        assert!(matches!(resolve_under_root(&root_path, "/missing.html").await, Err(FileOpenStatus::DNE)));
+        assert!(matches!(resolve_under_root(&root_path, "/escape/missing.txt").await, Err(FileOpenStatus::DNE)));
+        // paths through a regular file
+        assert!(matches!(resolve_under_root(&root_path, "/index.html/index.html").await, Err(FileOpenStatus::DNE)));
+        assert!(matches!(resolve_under_root(&root_path, "/docs/a.txt/b").await, Err(FileOpenStatus::DNE)));
+    }
+
+    #[tokio::test]
+    async fn missing_root_is_an_error(){
+        let (_root_path, root, _directory) = document_root();
+        let missing = root.join("missing");
+        assert!(matches!(resolve_under_root(missing.to_str().unwrap(), "/index.html").await, Err(FileOpenStatus::ERROR)));
+    }
+}
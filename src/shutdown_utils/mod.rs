@@ -1,13 +1,9 @@
-use std::future::{Future, IntoFuture};
-use std::pin::Pin;
-use std::process::Output;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 
-use futures::future::Then;
-use futures::FutureExt;
-use tokio;
-use std::marker::PhantomData;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use tokio::sync::Notify;
 
 /**
  * Future which resolves on ctrl+c user input.
@@ -23,9 +19,16 @@ pub async fn shutdown_on_ctrl_c(){
 
 /**
  * Helper struct to manage server shutdown.
+ * 
+ * Also counts the open connections of each remote address, so connection limits can be enforced.
+ * Clones share their counts, so a clone can report on the connections of a running server.
  */
+#[derive(Clone)]
 pub struct ShutdownHelper{
     active: Arc<AtomicU32>,
+    per_ip: Arc<Mutex<HashMap<IpAddr, u32>>>,
+    released: Arc<Notify>,
+    draining: Arc<AtomicBool>
 }
 
 impl ShutdownHelper{
@@ -35,6 +38,9 @@ impl ShutdownHelper{
     pub fn new() -> Self{
         Self{
             active: Arc::new(AtomicU32::new(0)),
+            per_ip: Arc::new(Mutex::new(HashMap::new())),
+            released: Arc::new(Notify::new()),
+            draining: Arc::new(AtomicBool::new(false))
         }
     }
 
@@ -43,13 +49,16 @@ impl ShutdownHelper{
      * 
      * This method returns a future which resolves when the connection is finished.
      * 
+     * # Arguments
+     * * `peer` - The address of the remote end of the connection.
+     * 
      * # Returns
      * A future which resolves when the connection is finished. 
      * 
      * # Example
      * ```
      * let shutdown_helper = ShutdownHelper::new();
-     * let connection = shutdown_helper.register();
+     * let connection = shutdown_helper.register(peer.ip());
      * 
      * tokio::spawn(async move {
      *    // do some works
@@ -57,17 +66,68 @@ impl ShutdownHelper{
      * });
      * ```
      */
-    pub fn register(&mut self) -> tokio::sync::oneshot::Sender<()>{
+    pub fn register(&mut self, peer: IpAddr) -> tokio::sync::oneshot::Sender<()>{
         let active = Arc::clone(&self.active);
+        let per_ip = Arc::clone(&self.per_ip);
+        let released = Arc::clone(&self.released);
         self.active.fetch_add(1, Ordering::SeqCst);
+        *self.per_ip.lock().unwrap().entry(peer).or_insert(0) += 1;
 
         let (tx, rx) = tokio::sync::oneshot::channel();
         tokio::spawn(async move {
-            rx.await.unwrap();
+            // a dropped sender also means the connection is finished
+            let _ = rx.await;
             active.fetch_sub(1, Ordering::SeqCst);
+            let mut per_ip = per_ip.lock().unwrap();
+            if let Some(count) = per_ip.get_mut(&peer){
+                *count -= 1;
+                if *count == 0{
+                    per_ip.remove(&peer);
+                }
+            }
+            released.notify_one();
         });
         tx
     }
+
+    /**
+     * The number of open connections.
+     */
+    pub fn active(&self) -> u32{
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /**
+     * The number of open connections from a remote address.
+     * 
+     * # Arguments
+     * * `peer` - The remote address.
+     */
+    pub fn active_for(&self, peer: IpAddr) -> u32{
+        self.per_ip.lock().unwrap().get(&peer).copied().unwrap_or(0)
+    }
+
+    /**
+     * Resolves when a connection has finished since the last call.
+     */
+    pub async fn released(&self){
+        self.released.notified().await
+    }
+
+    /**
+     * Mark the server as draining: it no longer accepts connections, and is finishing the open ones.
+     */
+    pub fn start_draining(&self){
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /**
+     * Whether the server has stopped accepting connections.
+     */
+    pub fn draining(&self) -> bool{
+        self.draining.load(Ordering::SeqCst)
+    }
+
     /**
      * Shutdown the server.
      * 
@@ -78,4 +138,4 @@ impl ShutdownHelper{
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
     }
-}
\ No newline at end of file
+}